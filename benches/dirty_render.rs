@@ -1,8 +1,10 @@
 extern crate criterion;
 
-use ar5iv::assemble_asset::assemble_paper;
+use ar5iv::assemble_asset::{assemble_paper, LatexmlStatus};
+use ar5iv::dirty_templates::dirty_branded_ar5iv_html;
 use criterion::Criterion;
 use criterion::{criterion_group, criterion_main};
+use regex::{Captures, Regex};
 use rocket::tokio::runtime::Runtime;
 
 fn dirty_prepare_bench(c: &mut Criterion) {
@@ -15,5 +17,95 @@ fn dirty_prepare_bench(c: &mut Criterion) {
   });
 }
 
-criterion_group!(benches, dirty_prepare_bench);
+/// A latexml-like document of roughly `sections` times 4KiB.
+fn synthetic_document(sections: usize) -> String {
+  let mut html = String::from(
+    "<!DOCTYPE html><html>\n<head>\n<title>A synthetic article</title>\n</head>\n<body>\n\
+<div class=\"ltx_page_main\">\n<div class=\"ltx_page_content\">\n<article class=\"ltx_document\">\n\
+<div class=\"ltx_abstract\"><p class=\"ltx_p\">We benchmark the branding of articles.</p></div>\n",
+  );
+  for i in 0..sections {
+    html.push_str(&format!(
+      "<section id=\"S{i}\" class=\"ltx_section\"><p class=\"ltx_p\">{}</p>\n\
+<img src=\"x{i}.png\" class=\"ltx_graphics\" alt=\"\">\n\
+<a href=\"https://arxiv.org/abs/{i}\" class=\"ltx_ref\">ref</a>\n\
+<math><mi>a</mi><mo>\u{2062}</mo><mi>b</mi></math></section>\n",
+      "Lorem ipsum dolor sit amet. ".repeat(128)
+    ));
+  }
+  html.push_str(
+    "</article>\n</div>\n<footer class=\"ltx_page_footer\"></footer>\n</div>\n</body>\n</html>\n",
+  );
+  html
+}
+
+/// The full-document regex passes the streaming rewriter replaced, for the
+/// "before" numbers: the same replacements, with stand-ins for the markup
+/// they inserted -- what they cost is the copying. (The exact reference is
+/// kept in the tests of `dirty_templates`.)
+fn regex_passes(document: String, id_arxiv: &str) -> String {
+  let assets = format!("/html/{id_arxiv}/assets/");
+  let mut html = document
+    .replacen("<html>", "<html lang=\"en\">", 1)
+    .replace(">\u{2062}", " lspace='0px' rspace='0px'>");
+  html = Regex::new("<title>((?s)[^<]+?)</title>")
+    .unwrap()
+    .replace(&html, |caps: &Captures| {
+      format!("<title>[{id_arxiv}] {}</title>", &caps[1])
+    })
+    .to_string();
+  html = Regex::new(" src=\"([^\"]+)")
+    .unwrap()
+    .replace_all(&html, |caps: &Captures| {
+      format!(" src=\"{assets}{}", &caps[1])
+    })
+    .to_string();
+  html = Regex::new(" data=\"([^\"]+)[.]svg")
+    .unwrap()
+    .replace_all(&html, |caps: &Captures| {
+      format!(" data=\"{assets}{}.svg", &caps[1])
+    })
+    .to_string();
+  for (pattern, replacement) in [
+    (" href=\"http", " target=\"_blank\" href=\"http"),
+    (
+      "<footer class=\"ltx_page_footer\">",
+      "<nav></nav><footer class=\"ltx_page_footer\">",
+    ),
+    ("</head>", "<link rel=\"stylesheet\"></head>"),
+    ("</body>", "<script></script></body>"),
+  ] {
+    html = Regex::new(pattern)
+      .unwrap()
+      .replace_all(&html, replacement)
+      .to_string();
+  }
+  html
+}
+
+fn branding_bench(c: &mut Criterion) {
+  // ~4MiB, the size of a sizeable article
+  let document = synthetic_document(1024);
+  let mut group = c.benchmark_group("branding");
+  group.bench_function("regex passes", |b| {
+    b.iter(|| regex_passes(document.clone(), "2105.04026"))
+  });
+  group.bench_function("streaming rewriter", |b| {
+    b.iter(|| {
+      dirty_branded_ar5iv_html(
+        document.clone(),
+        "2105.04026",
+        &[],
+        LatexmlStatus::Ok,
+        None,
+        None,
+        None,
+      )
+      .unwrap()
+    })
+  });
+  group.finish();
+}
+
+criterion_group!(benches, dirty_prepare_bench, branding_bench);
 criterion_main!(benches);
@@ -1,3 +1,4 @@
+use ar5iv::cache::split_arxiv_version;
 use ar5iv::paper_order::{AR5IV_PAPERS_ROOT_DIR, FIELD_BOUNDARY};
 use std::collections::HashSet;
 use walkdir::WalkDir;
@@ -32,7 +33,9 @@ fn main() -> redis::RedisResult<()> {
       let entry_path = entry.path();
       if entry_path.is_dir() {
         let id_like = entry_path.file_name().unwrap_or_default().to_string_lossy();
-        if id_like.len() > 4 && id_like != "arxmliv" {
+        // version-specific bundles (e.g. "2105.04404v3") sit next to their
+        // paper's default bundle; they get no navigation entry of their own.
+        if id_like.len() > 4 && id_like != "arxmliv" && split_arxiv_version(&id_like).1.is_none() {
           let id = FIELD_BOUNDARY.replace(&id_like, "$1/$2");
           if prev_prev.is_empty() && !prev.is_empty() && first.is_empty() {
             first = prev.to_string();
@@ -0,0 +1,201 @@
+use ar5iv::assemble_asset::{read_bundle_log, BundleKind, LatexmlStatus};
+use ar5iv::cache::split_arxiv_version;
+use ar5iv::conversion_log::ConversionLog;
+use ar5iv::paper_order::AR5IV_PAPERS_ROOT_DIR;
+use rocket::serde::json;
+use rocket::serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::error::Error;
+use std::fs;
+use walkdir::WalkDir;
+
+/// How many of the most frequent undefined macros and missing files to keep.
+const TOP_N: usize = 100;
+
+/// Per-month conversion outcomes.
+#[derive(Debug, Default, Serialize)]
+#[serde(crate = "rocket::serde")]
+struct MonthStats {
+  ok: usize,
+  warning: usize,
+  error: usize,
+  fatal: usize,
+  oxidized: usize,
+  legacy: usize,
+}
+impl MonthStats {
+  fn record(&mut self, status: LatexmlStatus, kind: BundleKind) {
+    match status {
+      LatexmlStatus::Ok => self.ok += 1,
+      LatexmlStatus::Warning => self.warning += 1,
+      LatexmlStatus::Error => self.error += 1,
+      LatexmlStatus::Fatal => self.fatal += 1,
+    }
+    match kind {
+      BundleKind::Oxidized => self.oxidized += 1,
+      BundleKind::Legacy => self.legacy += 1,
+    }
+  }
+  fn total(&self) -> usize {
+    self.ok + self.warning + self.error + self.fatal
+  }
+  fn fields(&self) -> [(&'static str, usize); 6] {
+    [
+      ("ok", self.ok),
+      ("warning", self.warning),
+      ("error", self.error),
+      ("fatal", self.fatal),
+      ("oxidized", self.oxidized),
+      ("legacy", self.legacy),
+    ]
+  }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(crate = "rocket::serde")]
+struct CorpusStats {
+  months: BTreeMap<String, MonthStats>,
+  undefined_macros: Vec<(String, usize)>,
+  missing_files: Vec<(String, usize)>,
+}
+
+/// Walks every paper bundle under `AR5IV_PAPERS_ROOT_DIR` and aggregates its
+/// conversion log. Writes `<prefix>.json` and `<prefix>.csv` (the prefix is the
+/// optional first argument, "conversion_stats" by default), and mirrors the
+/// results into Redis under `conversion_stats:*`.
+fn main() -> Result<(), Box<dyn Error>> {
+  let output_prefix = env::args()
+    .nth(1)
+    .unwrap_or_else(|| String::from("conversion_stats"));
+  let mut months: BTreeMap<String, MonthStats> = BTreeMap::new();
+  let mut undefined_macros: HashMap<String, usize> = HashMap::new();
+  let mut missing_files: HashMap<String, usize> = HashMap::new();
+  let mut current_month = String::new();
+
+  let walker = WalkDir::new(AR5IV_PAPERS_ROOT_DIR.to_string())
+    .min_depth(2)
+    .max_depth(2)
+    .sort_by_file_name()
+    .follow_links(true);
+  for entry in walker.into_iter().flatten() {
+    let entry_path = entry.path();
+    if !entry_path.is_dir() {
+      continue;
+    }
+    let id_like = entry_path.file_name().unwrap_or_default().to_string_lossy();
+    // as in cache_adjacency_map: papers only, no version-specific bundles.
+    if id_like.len() <= 4 || id_like == "arxmliv" || split_arxiv_version(&id_like).1.is_some() {
+      continue;
+    }
+    let month = entry_path
+      .parent()
+      .and_then(|parent| parent.file_name())
+      .map(|name| name.to_string_lossy().to_string())
+      .unwrap_or_default();
+    if month != current_month {
+      report_progress(&current_month, months.get(&current_month));
+      current_month = month.clone();
+    }
+    // the bundle generation expected for the month, else whichever is present.
+    let expected = BundleKind::of(&month);
+    let other = match expected {
+      BundleKind::Oxidized => BundleKind::Legacy,
+      BundleKind::Legacy => BundleKind::Oxidized,
+    };
+    let Some(kind) = [expected, other]
+      .into_iter()
+      .find(|kind| entry_path.join(kind.file_name()).exists())
+    else {
+      continue;
+    };
+    let log = read_bundle_log(&entry_path.join(kind.file_name())).unwrap_or_default();
+    let parsed = ConversionLog::parse(&log);
+    // a missing log counts as fatal, just like on the article pages.
+    let status = if log.is_empty() {
+      LatexmlStatus::Fatal
+    } else {
+      parsed.status()
+    };
+    for name in parsed.objects_of("undefined") {
+      *undefined_macros.entry(name.to_string()).or_default() += 1;
+    }
+    for name in parsed.objects_of("missing_file") {
+      *missing_files.entry(name.to_string()).or_default() += 1;
+    }
+    months.entry(month).or_default().record(status, kind);
+  }
+  report_progress(&current_month, months.get(&current_month));
+
+  let stats = CorpusStats {
+    months,
+    undefined_macros: most_frequent(undefined_macros),
+    missing_files: most_frequent(missing_files),
+  };
+  fs::write(
+    format!("{output_prefix}.json"),
+    json::to_pretty_string(&stats)?,
+  )?;
+  fs::write(format!("{output_prefix}.csv"), months_csv(&stats.months))?;
+  save_to_cache(&stats)?;
+  Ok(())
+}
+
+fn report_progress(month: &str, stats: Option<&MonthStats>) {
+  if let Some(stats) = stats {
+    println!(
+      "{month}: {} papers ({} ok, {} warning, {} error, {} fatal)",
+      stats.total(),
+      stats.ok,
+      stats.warning,
+      stats.error,
+      stats.fatal
+    );
+  }
+}
+
+fn most_frequent(counts: HashMap<String, usize>) -> Vec<(String, usize)> {
+  let mut counts: Vec<(String, usize)> = counts.into_iter().collect();
+  counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+  counts.truncate(TOP_N);
+  counts
+}
+
+fn months_csv(months: &BTreeMap<String, MonthStats>) -> String {
+  let mut csv = String::from("month,ok,warning,error,fatal,oxidized,legacy\n");
+  for (month, stats) in months.iter() {
+    csv.push_str(month);
+    for (_, count) in stats.fields() {
+      csv.push(',');
+      csv.push_str(&count.to_string());
+    }
+    csv.push('\n');
+  }
+  csv
+}
+
+fn save_to_cache(stats: &CorpusStats) -> redis::RedisResult<()> {
+  let client = redis::Client::open("redis://127.0.0.1/")?;
+  let mut conn = client.get_connection()?;
+  let mut pipe = redis::pipe();
+  for (month, month_stats) in stats.months.iter() {
+    pipe
+      .hset_multiple(format!("conversion_stats:{month}"), &month_stats.fields())
+      .ignore();
+  }
+  for (key, counts) in [
+    ("conversion_stats:undefined_macros", &stats.undefined_macros),
+    ("conversion_stats:missing_files", &stats.missing_files),
+  ] {
+    // replace, rather than accumulate onto, the previous run's rankings
+    pipe.del(key).ignore();
+    if !counts.is_empty() {
+      let members: Vec<(usize, &str)> = counts
+        .iter()
+        .map(|(name, count)| (*count, name.as_str()))
+        .collect();
+      pipe.zadd_multiple(key, &members).ignore();
+    }
+  }
+  pipe.query(&mut conn)
+}
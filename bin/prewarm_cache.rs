@@ -0,0 +1,176 @@
+use ar5iv::assemble_asset::assemble_paper_warming;
+use ar5iv::assemble_error::AssembleError;
+use ar5iv::cache::{split_arxiv_field, split_arxiv_version, CacheConfig};
+use ar5iv::cache_backend::PaperCache;
+use ar5iv::paper_order::{AR5IV_PAPERS_ROOT_DIR, FIELD_BOUNDARY};
+use rocket::tokio::task::{JoinError, JoinSet};
+use rocket_db_pools::deadpool_redis::{Config, Runtime};
+use std::env;
+use std::error::Error;
+use std::path::Path;
+use std::time::Instant;
+use walkdir::WalkDir;
+
+/// How many papers are assembled at once, unless `--jobs` says otherwise.
+const DEFAULT_JOBS: usize = 4;
+/// How often (in papers) progress is reported.
+const REPORT_EVERY: usize = 100;
+
+/// Fills the cache with the branded HTML, logs and assets of papers, through
+/// the same `assemble_paper` path a request takes -- e.g. for a newly released
+/// month, before crawlers find it cold:
+///
+///   prewarm_cache [--jobs N] <YYMM | id>...
+///
+/// Months are walked under `AR5IV_PAPERS_ROOT_DIR`; the cache backend, its
+/// lifetimes and the Redis URL come from Rocket.toml, as for the server.
+#[rocket::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+  let mut jobs = DEFAULT_JOBS;
+  let mut ids = Vec::new();
+  // (`Args` isn't `Send`: collected before the first await)
+  let mut args = env::args().skip(1).collect::<Vec<_>>().into_iter();
+  while let Some(arg) = args.next() {
+    if arg == "--jobs" || arg == "-j" {
+      jobs = args
+        .next()
+        .and_then(|n| n.parse().ok())
+        .filter(|n| *n > 0)
+        .ok_or("--jobs takes a positive number")?;
+    } else if arg.len() == 4 && arg.bytes().all(|b| b.is_ascii_digit()) {
+      ids.extend(month_ids(&arg));
+    } else {
+      ids.push(arg);
+    }
+  }
+  if ids.is_empty() {
+    return Err("usage: prewarm_cache [--jobs N] <YYMM | id>...".into());
+  }
+
+  let figment = rocket::Config::figment();
+  let config = CacheConfig::load(&figment);
+  if config.backend == "memory" {
+    return Err("the memory cache lives in the server: prewarm it with POST /admin/prewarm".into());
+  }
+  let url = figment
+    .extract_inner::<String>("databases.memdb.url")
+    .unwrap_or_else(|_| String::from("redis://127.0.0.1/"));
+  let pool = Config::from_url(url).create_pool(Some(Runtime::Tokio1))?;
+  if config.backend != "disk" {
+    // fail early, rather than assemble every paper for nothing
+    pool.get().await?;
+  }
+  let cache = config
+    .backend(Some(pool))
+    .ok_or("no cache backend to prewarm")?;
+
+  let mut progress = Progress::new(ids.len());
+  let mut tasks: JoinSet<(String, bool)> = JoinSet::new();
+  for id_arxiv in ids {
+    if tasks.len() >= jobs {
+      if let Some(joined) = tasks.join_next().await {
+        progress.joined(joined);
+      }
+    }
+    tasks.spawn(prewarm(cache.clone(), id_arxiv));
+  }
+  while let Some(joined) = tasks.join_next().await {
+    progress.joined(joined);
+  }
+  progress.report();
+  Ok(())
+}
+
+/// Assembles a paper, and waits for its assets and log to be cached too.
+async fn prewarm(cache: PaperCache, id_arxiv: String) -> (String, bool) {
+  let (field_opt, id) = split_arxiv_field(&id_arxiv);
+  let warmed = match assemble_paper_warming(Some(cache), field_opt, id).await {
+    Ok((_, _, warm_up)) => {
+      warm_up.await;
+      true
+    }
+    Err(AssembleError::NotFound) => false,
+    Err(e) => {
+      eprintln!("{id_arxiv}: {e}");
+      false
+    }
+  };
+  (id_arxiv, warmed)
+}
+
+/// The papers of a month, in order.
+fn month_ids(month: &str) -> Vec<String> {
+  WalkDir::new(Path::new(AR5IV_PAPERS_ROOT_DIR.as_str()).join(month))
+    .min_depth(1)
+    .max_depth(1)
+    .sort_by_file_name()
+    .follow_links(true)
+    .into_iter()
+    .flatten()
+    .filter(|entry| entry.path().is_dir())
+    .filter_map(|entry| {
+      let id_like = entry.file_name().to_string_lossy();
+      // as in cache_adjacency_map: papers only, no version-specific bundles.
+      if id_like.len() <= 4 || split_arxiv_version(&id_like).1.is_some() {
+        None
+      } else {
+        Some(FIELD_BOUNDARY.replace(&id_like, "$1/$2").into_owned())
+      }
+    })
+    .collect()
+}
+
+struct Progress {
+  total: usize,
+  done: usize,
+  missing: usize,
+  failed: usize,
+  start: Instant,
+}
+impl Progress {
+  fn new(total: usize) -> Self {
+    Progress {
+      total,
+      done: 0,
+      missing: 0,
+      failed: 0,
+      start: Instant::now(),
+    }
+  }
+  fn record(&mut self, id_arxiv: &str, warmed: bool) {
+    self.done += 1;
+    if !warmed {
+      self.missing += 1;
+      eprintln!("{id_arxiv}: no bundle could be assembled");
+    }
+    if self.done.is_multiple_of(REPORT_EVERY) {
+      self.report();
+    }
+  }
+  /// Records a prewarm task as it ends, panicked ones included (their paper
+  /// is unknown by then, but still counts toward the total).
+  fn joined(&mut self, joined: Result<(String, bool), JoinError>) {
+    match joined {
+      Ok((id_arxiv, warmed)) => self.record(&id_arxiv, warmed),
+      Err(e) => {
+        self.done += 1;
+        self.failed += 1;
+        eprintln!("a prewarm task failed: {e}");
+        if self.done.is_multiple_of(REPORT_EVERY) {
+          self.report();
+        }
+      }
+    }
+  }
+  fn report(&self) {
+    let elapsed = self.start.elapsed().as_secs_f64();
+    println!(
+      "{}/{} papers ({} missing, {} failed) in {elapsed:.0}s, {:.1} papers/s",
+      self.done,
+      self.total,
+      self.missing,
+      self.failed,
+      self.done as f64 / elapsed.max(0.001)
+    );
+  }
+}
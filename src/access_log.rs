@@ -0,0 +1,297 @@
+use rocket::fairing::{AdHoc, Fairing, Info, Kind};
+use rocket::http::{Header, Status};
+use rocket::request::{self, FromRequest};
+use rocket::serde::json;
+use rocket::serde::{Deserialize, Serialize};
+use rocket::{warn, Data, Request, Response};
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::assemble_asset::{AssemblyStats, BundleKind};
+use crate::assemble_error::AssembleError;
+use crate::cache_backend::CacheTier;
+use crate::metrics::RequestStart;
+
+/// Request ids are unique to a launch: its time, and a counter.
+static LAUNCH: LazyLock<u64> = LazyLock::new(|| {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map_or(0, |since| since.as_secs())
+});
+static REQUESTS: AtomicU64 = AtomicU64::new(0);
+
+/// The `[access_log]` table of Rocket.toml: whether to log every request as a
+/// JSON line, and where to (appending to `path`; stdout when empty).
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct AccessLogConfig {
+  pub enabled: bool,
+  pub path: String,
+}
+
+impl Default for AccessLogConfig {
+  fn default() -> Self {
+    AccessLogConfig {
+      enabled: true,
+      path: String::new(),
+    }
+  }
+}
+
+/// One line of the access log.
+#[derive(Debug, Default, Serialize)]
+#[serde(crate = "rocket::serde")]
+struct AccessRecord {
+  /// Unix time of the response, in milliseconds.
+  ts: u128,
+  id: String,
+  method: String,
+  path: String,
+  route: Option<String>,
+  status: u16,
+  duration_ms: f64,
+  arxiv_id: Option<String>,
+  bundle: Option<BundleKind>,
+  /// `hot`, `backend`, or `miss` -- for the routes reading the paper cache.
+  cache: Option<&'static str>,
+  /// What the assembly of a missed paper took.
+  decompressed_bytes: Option<u64>,
+  assets: Option<usize>,
+  decompress_ms: Option<f64>,
+  branding_ms: Option<f64>,
+  /// Why the paper could not be assembled, when it could not.
+  error: Option<String>,
+  outcome: &'static str,
+}
+
+/// The access log record of a request, filled in by the routes and the cache
+/// as they go, and written out with the response.
+#[derive(Debug, Clone, Default)]
+pub struct RequestLog(Arc<Mutex<AccessRecord>>);
+
+impl RequestLog {
+  fn with(&self, update: impl FnOnce(&mut AccessRecord)) {
+    if let Ok(mut record) = self.0.lock() {
+      update(&mut record);
+    }
+  }
+
+  /// The paper requested, and the generation of the bundle found for it --
+  /// `None` for a paper we don't hold.
+  pub fn paper(&self, id_arxiv: &str, bundle: Option<BundleKind>) {
+    self.with(|record| {
+      record.arxiv_id = Some(id_arxiv.to_string());
+      record.bundle = bundle;
+    });
+  }
+
+  /// The tier of the cache a response came from -- `None` for a miss.
+  pub fn cache(&self, tier: Option<CacheTier>) {
+    self.with(|record| {
+      record.cache = Some(match tier {
+        Some(CacheTier::Hot) => "hot",
+        Some(CacheTier::Backend) => "backend",
+        None => "miss",
+      });
+    });
+  }
+
+  pub fn assembly(&self, stats: &AssemblyStats) {
+    self.with(|record| {
+      record.decompressed_bytes = Some(stats.decompressed_bytes);
+      record.assets = Some(stats.assets);
+      record.decompress_ms = Some(millis(stats.decompress));
+      record.branding_ms = Some(millis(stats.branding));
+    });
+  }
+
+  pub fn error(&self, error: &AssembleError) {
+    self.with(|record| record.error = Some(format!("{}: {error}", error.kind())));
+  }
+
+  /// The id of the request, as sent back in `X-Request-Id`.
+  pub fn request_id(&self) -> String {
+    self
+      .0
+      .lock()
+      .map_or_else(|_| String::new(), |record| record.id.clone())
+  }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RequestLog {
+  type Error = ();
+  async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, ()> {
+    request::Outcome::Success(req.local_cache(RequestLog::default).clone())
+  }
+}
+
+fn millis(duration: Duration) -> f64 {
+  duration.as_micros() as f64 / 1000.0
+}
+
+/// What became of a request, by its response.
+fn outcome(status: Status, location: Option<&str>) -> &'static str {
+  match status.code {
+    304 => "not_modified",
+    300..=399 if location.is_some_and(|to| to.starts_with("https://arxiv.org/")) => {
+      "redirected_to_arxiv"
+    }
+    300..=399 => "redirected",
+    404 => "not_found",
+    503 => "unavailable",
+    500..=599 => "error",
+    _ => "served",
+  }
+}
+
+/// Writes a JSON line per request, with its id (the `X-Request-Id` it came
+/// with, or a new one, sent back either way), its route and timing, and what
+/// the routes recorded of the paper served.
+pub struct AccessLog {
+  out: Mutex<Box<dyn Write + Send>>,
+}
+
+impl AccessLog {
+  /// Reads the `[access_log]` table, and attaches the log if enabled.
+  pub fn fairing() -> AdHoc {
+    AdHoc::on_ignite("Access log config", |rocket| async {
+      let config = match rocket
+        .figment()
+        .extract_inner::<AccessLogConfig>("access_log")
+      {
+        Ok(config) => config,
+        Err(e) if e.missing() => AccessLogConfig::default(),
+        Err(e) => {
+          warn!("invalid [access_log] configuration, using the defaults: {e}");
+          AccessLogConfig::default()
+        }
+      };
+      if !config.enabled {
+        return rocket;
+      }
+      let out: Box<dyn Write + Send> = if config.path.is_empty() {
+        Box::new(io::stdout())
+      } else {
+        match OpenOptions::new()
+          .create(true)
+          .append(true)
+          .open(&config.path)
+        {
+          Ok(file) => Box::new(file),
+          Err(e) => {
+            warn!(
+              "cannot open the access log {}, logging to stdout: {e}",
+              config.path
+            );
+            Box::new(io::stdout())
+          }
+        }
+      };
+      rocket.attach(AccessLog {
+        out: Mutex::new(out),
+      })
+    })
+  }
+}
+
+#[rocket::async_trait]
+impl Fairing for AccessLog {
+  fn info(&self) -> Info {
+    Info {
+      name: "Access log",
+      kind: Kind::Request | Kind::Response,
+    }
+  }
+
+  async fn on_request(&self, req: &mut Request<'_>, _: &mut Data<'_>) {
+    req.local_cache(|| RequestStart(Instant::now()));
+    let id = match req.headers().get_one("X-Request-Id") {
+      Some(id) if !id.is_empty() && id.len() <= 128 => id.to_string(),
+      _ => format!(
+        "{:x}-{:x}",
+        *LAUNCH,
+        REQUESTS.fetch_add(1, Ordering::Relaxed)
+      ),
+    };
+    req
+      .local_cache(RequestLog::default)
+      .with(|record| record.id = id);
+  }
+
+  async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+    let elapsed = req.local_cache(|| RequestStart(Instant::now())).0.elapsed();
+    let log = req.local_cache(RequestLog::default);
+    let Ok(mut record) = log.0.lock() else {
+      return;
+    };
+    record.ts = SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .map_or(0, |since| since.as_millis());
+    record.method = req.method().to_string();
+    record.path = req.uri().path().to_string();
+    record.route = req
+      .route()
+      .and_then(|route| route.name.as_ref())
+      .map(|name| name.to_string());
+    record.status = res.status().code;
+    record.duration_ms = millis(elapsed);
+    record.outcome = outcome(res.status(), res.headers().get_one("Location"));
+    res.set_header(Header::new("X-Request-Id", record.id.clone()));
+    let Ok(line) = json::to_string(&*record) else {
+      return;
+    };
+    drop(record);
+    if let Ok(mut out) = self.out.lock() {
+      writeln!(out, "{line}").ok();
+      out.flush().ok();
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn outcomes_tell_redirects_to_arxiv_apart() {
+    let arxiv = Some("https://arxiv.org/abs/2105.04404");
+    assert_eq!(
+      outcome(Status::TemporaryRedirect, arxiv),
+      "redirected_to_arxiv"
+    );
+    assert_eq!(
+      outcome(Status::SeeOther, Some("/html/2105.04404")),
+      "redirected"
+    );
+    assert_eq!(outcome(Status::NotModified, None), "not_modified");
+    assert_eq!(outcome(Status::NotFound, None), "not_found");
+    assert_eq!(outcome(Status::ServiceUnavailable, None), "unavailable");
+    assert_eq!(outcome(Status::PartialContent, None), "served");
+  }
+
+  #[test]
+  fn records_are_single_json_lines() {
+    let log = RequestLog::default();
+    log.paper("2105.04404", Some(BundleKind::Legacy));
+    log.cache(None);
+    log.assembly(&AssemblyStats {
+      decompressed_bytes: 123_456,
+      assets: 7,
+      decompress: Duration::from_millis(40),
+      branding: Duration::from_millis(12),
+    });
+    let line = json::to_string(&*log.0.lock().unwrap()).unwrap();
+    assert!(!line.contains('\n'));
+    let record: json::Value = json::from_str(&line).unwrap();
+    assert_eq!(record["arxiv_id"], "2105.04404");
+    assert_eq!(record["bundle"], "legacy");
+    assert_eq!(record["cache"], "miss");
+    assert_eq!(record["decompressed_bytes"], 123_456);
+    assert_eq!(record["assets"], 7);
+    assert_eq!(record["branding_ms"], 12.0);
+  }
+}
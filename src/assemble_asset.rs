@@ -1,20 +1,33 @@
-use rocket::fs::NamedFile;
+use regex::Regex;
+use rocket::http::ContentType;
+use rocket::serde::Serialize;
+use rocket::tokio::fs::File as AsyncFile;
 use rocket::tokio::task::spawn_blocking;
-use rocket_db_pools::Connection;
 use std::fs::File;
+use std::future::Future;
 use std::io::{BufReader, Read};
 use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+use std::time::{Duration, Instant};
 use zip::ZipArchive;
 
+use crate::assemble_error::AssembleError;
 use crate::cache::{
-  asset_key, build_arxiv_id, hget_cached, log_key, paper_key, set_cached, set_cached_asset, Cache,
-  SIXTY_FOUR_MIB, TEN_MIB,
+  asset_key, build_arxiv_id, bundle_fingerprint, hget_cached, log_key, page_key, paper_key,
+  set_cached, set_cached_asset, split_arxiv_version, SIXTY_FOUR_MIB, TEN_MIB,
 };
+use crate::cache_backend::{PaperCache, SharedBytes};
+use crate::conditional::{Ranged, Validators};
 use crate::constants::{uses_oxidized_bundle, LOG_FILENAME};
-use crate::dirty_templates::{dirty_branded_ar5iv_html, log_to_html};
+use crate::conversion_log::{source_version, ConversionLog};
+use crate::dirty_templates::{dirty_branded_ar5iv_html, log_to_html, MissingVersion};
+use crate::metrics::METRICS;
 use crate::paper_order::AR5IV_PAPERS_ROOT_DIR;
+use crate::sniff::SNIFF_LEN;
+use crate::zip_index::ZIP_INDEXES;
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(crate = "rocket::serde", rename_all = "lowercase")]
 pub enum LatexmlStatus {
   Ok,
   Warning,
@@ -32,28 +45,120 @@ impl LatexmlStatus {
   }
 }
 
+/// The two generations of result bundles on disk: latexml-oxide months (see
+/// `OXIDIZED_BUNDLE_MIN_YYMM`) and the legacy latexml ones before them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(crate = "rocket::serde", rename_all = "lowercase")]
+pub enum BundleKind {
+  Oxidized,
+  Legacy,
+}
+impl BundleKind {
+  pub fn of(id_arxiv: &str) -> Self {
+    if uses_oxidized_bundle(id_arxiv) {
+      BundleKind::Oxidized
+    } else {
+      BundleKind::Legacy
+    }
+  }
+  pub fn file_name(&self) -> &'static str {
+    match self {
+      BundleKind::Oxidized => "oxidized_tex_to_html.zip",
+      BundleKind::Legacy => "tex_to_html.zip",
+    }
+  }
+}
+
+/// A paper's result bundle on disk, as resolved for a (possibly versioned) id.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PaperBundle {
+  pub path: PathBuf,
+  pub kind: BundleKind,
+  /// The id (sans legacy field) whose bundle is on disk: the requested id when
+  /// we hold that exact arXiv version, otherwise the version-less id of the
+  /// default bundle.
+  pub id: String,
+  /// The requested version (e.g. "v3"), when we don't hold it and serve the
+  /// default bundle in its place.
+  pub missing_version: Option<String>,
+}
+
 /// The pieces of a paper's ZIP that we extract for serving.
 struct PaperParts {
   html: String,
+  pages: Vec<String>,
   log: String,
   assets: Vec<(String, Vec<u8>)>,
 }
 
+/// What a paper's assembly took, for the access log.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AssemblyStats {
+  /// Bytes read out of the bundle: the main page, the log and the assets.
+  pub decompressed_bytes: u64,
+  pub assets: usize,
+  pub decompress: Duration,
+  pub branding: Duration,
+}
+
+/// `<link rel="start" href="main.html">`: how the pages of a split latexml
+/// conversion point back at its main page.
+static START_LINK: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"<link\s[^>]*>").unwrap());
+static START_REL: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"\srel="start""#).unwrap());
+static HREF_ATTR: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"\shref="([^"]+)""#).unwrap());
+
 pub async fn assemble_paper(
-  mut conn_opt: Option<Connection<Cache>>,
+  cache_opt: Option<PaperCache>,
   field_opt: Option<&str>,
   id: &str,
-) -> Option<String> {
-  let paper_path = build_paper_path(field_opt, id)?;
-  let id_arxiv = build_arxiv_id(&field_opt, id);
+) -> Result<String, AssembleError> {
+  let (branded_html, _, warm_up) = assemble_paper_warming(cache_opt, field_opt, id).await?;
+  // Warm the paper, asset and log caches in a detached task, off this request's
+  // critical path -- the browser will start fetching the assets as soon as
+  // it receives the HTML we are about to return.
+  rocket::tokio::spawn(warm_up);
+  Ok(branded_html)
+}
+
+/// `assemble_paper`, handing the warm-up of the paper, asset and log caches
+/// back to the caller -- to be awaited when there is no browser to wait for,
+/// as when prewarming -- along with what the assembly took.
+pub async fn assemble_paper_warming(
+  cache_opt: Option<PaperCache>,
+  field_opt: Option<&str>,
+  id: &str,
+) -> Result<
+  (
+    String,
+    AssemblyStats,
+    impl Future<Output = ()> + Send + 'static,
+  ),
+  AssembleError,
+> {
+  let PaperBundle {
+    path: paper_path,
+    id: id_bundle,
+    missing_version,
+    ..
+  } = build_paper_path(field_opt, id)?;
+  // the requested id keys the paper cache; the bundle's own id is what the
+  // page links its assets and log under; the version-less id is its place in
+  // the `paper_order` navigation.
+  let id_requested = build_arxiv_id(&field_opt, id);
+  let id_arxiv = build_arxiv_id(&field_opt, &id_bundle);
+  let id_unversioned = build_arxiv_id(&field_opt, split_arxiv_version(id).0);
+  // everything cached from the bundle is stored under its fingerprint
+  let fingerprint = fingerprint_of(&paper_path)?;
   // Open, scan and decompress the ZIP entirely inside a blocking task:
   // decompression is CPU-bound work that would otherwise stall the async workers.
-  // I/O errors (e.g. a ZIP being replaced mid-request by a data update)
-  // degrade to None instead of panicking.
-  let parts = spawn_blocking(move || -> Option<PaperParts> {
-    let zipf = File::open(paper_path).ok()?;
+  // I/O errors (e.g. a ZIP being replaced mid-request by a data update) are
+  // returned, rather than panicking.
+  let decompressing = Instant::now();
+  let parts = spawn_blocking(move || -> Result<PaperParts, AssembleError> {
+    let zipf = File::open(paper_path)?;
     let reader = BufReader::new(zipf);
-    let mut zip = ZipArchive::new(reader).ok()?;
+    let mut zip = ZipArchive::new(reader)?;
+    let pages = bundle_pages(&mut zip);
     let mut html = String::new();
     let mut log = String::new();
     let mut assets = Vec::new();
@@ -63,11 +168,12 @@ pub async fn assemble_paper(
           let mut asset = None;
           match file.name() {
             name if name.ends_with(".html") => {
-              if html.is_empty() {
+              if let Some(main_page) = pages.first().filter(|main_page| *main_page == name) {
                 // a damaged main document makes the paper unusable.
-                file.read_to_string(&mut html).ok()?;
+                html = AssembleError::read_text(&mut file, main_page)?;
               }
-              // (additional .html entries are ignored)
+              // (the other pages are branded when requested, see
+              // `assemble_paper_page`)
             }
             name if name == LOG_FILENAME => {
               // a damaged log is survivable.
@@ -82,6 +188,8 @@ pub async fn assemble_paper(
               // serve them from the ZIP on demand instead.
               if file.size() <= TEN_MIB as u64 {
                 asset = Some(other.to_string());
+              } else {
+                METRICS.inc("ar5iv_skipped_assets_total", &[("cap", "ten_mib")]);
               }
             }
           }
@@ -95,12 +203,28 @@ pub async fn assemble_paper(
         }
       }
     }
-    Some(PaperParts { html, log, assets })
+    Ok(PaperParts {
+      html,
+      pages,
+      log,
+      assets,
+    })
   })
-  .await
-  .ok()
-  .flatten()?;
-  let PaperParts { html, log, assets } = parts;
+  .await??;
+  let decompress = decompressing.elapsed();
+  METRICS.observe_duration(
+    "ar5iv_assembly_duration_seconds",
+    &[("stage", "decompress")],
+    decompress,
+  );
+  let PaperParts {
+    html,
+    pages,
+    log,
+    assets,
+  } = parts;
+  let decompressed_bytes =
+    html.len() + log.len() + assets.iter().map(|(_, asset)| asset.len()).sum::<usize>();
   // the log determines the conversion-status badge for the footer.
   let status = if log.is_empty() {
     LatexmlStatus::Fatal
@@ -108,8 +232,201 @@ pub async fn assemble_paper(
     log_to_status(&log)
   };
   // fish out the prev/next paper ids for the footer navigation.
-  let mut pieces: Vec<String> = if let Some(ref mut conn) = conn_opt {
-    if let Ok(adjacent_papers) = hget_cached(conn, "paper_order", &id_arxiv).await {
+  let adjacent = adjacent_papers(cache_opt.as_ref(), &id_unversioned).await;
+  let missing_version = missing_version.map(|requested| MissingVersion {
+    requested,
+    served: source_version(&log, &id_bundle),
+  });
+  let branding = Instant::now();
+  let branded_html = brand_page(
+    html,
+    id_arxiv.clone(),
+    pages,
+    status,
+    adjacent,
+    missing_version,
+  )
+  .await?;
+  let branding = branding.elapsed();
+  METRICS.observe_duration(
+    "ar5iv_assembly_duration_seconds",
+    &[("stage", "brand")],
+    branding,
+  );
+  let stats = AssemblyStats {
+    decompressed_bytes: decompressed_bytes as u64,
+    assets: assets.len(),
+    decompress,
+    branding,
+  };
+  METRICS.observe(
+    "ar5iv_assembled_paper_bytes",
+    &[],
+    branded_html.len() as f64,
+  );
+  let paper = branded_html.clone();
+  let warm_up = async move {
+    let Some(cache) = cache_opt else {
+      return;
+    };
+    // Cache the paper itself first, so the next request is a fast cache hit
+    // (compressing it is what keeps it off this request's critical path).
+    set_cached(&cache, &paper_key(&id_requested), &fingerprint, &paper)
+      .await
+      .ok();
+    drop(paper);
+    for (name, val) in assets.into_iter() {
+      if val.len() <= TEN_MIB {
+        // cap cache items at 10 MiB
+        let cache_key = asset_key(&id_arxiv, &name);
+        set_cached_asset(&cache, cache_key.as_str(), &fingerprint, &val)
+          .await
+          .ok();
+      }
+    }
+    if !log.is_empty() {
+      let html_log = log_to_html(&ConversionLog::parse(&log), &id_arxiv);
+      set_cached(&cache, &log_key(&id_arxiv), &fingerprint, &html_log)
+        .await
+        .ok();
+    }
+  };
+  Ok((branded_html, stats, warm_up))
+}
+
+/// One of the other HTML pages of a multi-page conversion (e.g. a chapter of
+/// a book), branded like its main page.
+pub async fn assemble_paper_page(
+  cache_opt: Option<PaperCache>,
+  field_opt: Option<&str>,
+  id: &str,
+  page: &str,
+) -> Result<String, AssembleError> {
+  let PaperBundle {
+    path: paper_path,
+    id: id_bundle,
+    missing_version,
+    ..
+  } = build_paper_path(field_opt, id)?;
+  let id_requested = build_arxiv_id(&field_opt, id);
+  let id_arxiv = build_arxiv_id(&field_opt, &id_bundle);
+  let id_unversioned = build_arxiv_id(&field_opt, split_arxiv_version(id).0);
+  let fingerprint = fingerprint_of(&paper_path)?;
+  let page_name = page.to_string();
+  let (html, pages, log) = spawn_blocking(move || -> Result<_, AssembleError> {
+    let zipf = File::open(paper_path)?;
+    let mut zip = ZipArchive::new(BufReader::new(zipf))?;
+    let pages = bundle_pages(&mut zip);
+    if !pages.contains(&page_name) {
+      return Err(AssembleError::NotFound);
+    }
+    let html = AssembleError::read_text(&mut zip.by_name(&page_name)?, &page_name)?;
+    let mut log = String::new();
+    if let Ok(mut file) = zip.by_name(LOG_FILENAME) {
+      // a damaged log is survivable.
+      if file.read_to_string(&mut log).is_err() {
+        log.clear();
+      }
+    }
+    Ok((html, pages, log))
+  })
+  .await??;
+  let status = if log.is_empty() {
+    LatexmlStatus::Fatal
+  } else {
+    log_to_status(&log)
+  };
+  let adjacent = adjacent_papers(cache_opt.as_ref(), &id_unversioned).await;
+  let missing_version = missing_version.map(|requested| MissingVersion {
+    requested,
+    served: source_version(&log, &id_bundle),
+  });
+  let branded_html = brand_page(html, id_arxiv, pages, status, adjacent, missing_version).await?;
+  if let Some(ref cache) = cache_opt {
+    set_cached(
+      cache,
+      &page_key(&id_requested, page),
+      &fingerprint,
+      branded_html.as_str(),
+    )
+    .await
+    .ok();
+  }
+  Ok(branded_html)
+}
+
+/// Builds a single coherent HTML page -- off the async workers, since
+/// branding is CPU-bound.
+async fn brand_page(
+  html: String,
+  id_arxiv: String,
+  pages: Vec<String>,
+  status: LatexmlStatus,
+  (prev, next): (Option<String>, Option<String>),
+  missing_version: Option<MissingVersion>,
+) -> Result<String, AssembleError> {
+  spawn_blocking(move || {
+    dirty_branded_ar5iv_html(html, &id_arxiv, &pages, status, prev, next, missing_version)
+  })
+  .await?
+}
+
+/// The fingerprint of a bundle, which was there a moment ago.
+fn fingerprint_of(paper_path: &Path) -> Result<String, AssembleError> {
+  bundle_fingerprint(paper_path)
+    .ok_or_else(|| AssembleError::Io(format!("{} went away", paper_path.display())))
+}
+
+/// The top-level HTML pages of a bundle, its main page first: a single page,
+/// else `index.html`, else the page the others name as their start, else the
+/// first by name -- never by ZIP order, which is up to the archiver.
+/// (Pages in subdirectories are served as assets, if at all.)
+pub(crate) fn bundle_pages<R: Read + std::io::Seek>(zip: &mut ZipArchive<R>) -> Vec<String> {
+  let mut pages: Vec<String> = zip
+    .file_names()
+    .filter(|name| name.ends_with(".html") && !name.contains('/'))
+    .map(str::to_string)
+    .collect();
+  pages.sort();
+  if pages.len() > 1 {
+    let main_page = if pages.iter().any(|page| page == "index.html") {
+      Some(String::from("index.html"))
+    } else {
+      // the start link is in every page but the main one, so this reads a
+      // page or two at most
+      pages.iter().find_map(|page| {
+        let mut html = String::new();
+        zip.by_name(page).ok()?.read_to_string(&mut html).ok()?;
+        start_page(&html).filter(|start| *start != *page && pages.contains(start))
+      })
+    };
+    if let Some(at) = main_page.and_then(|main_page| pages.iter().position(|p| *p == main_page)) {
+      let main_page = pages.remove(at);
+      pages.insert(0, main_page);
+    }
+  }
+  pages
+}
+
+/// The target of a page's `<link rel="start">`, if it has one.
+fn start_page(html: &str) -> Option<String> {
+  let head = &html[..html.find("</head>").unwrap_or(html.len())];
+  START_LINK
+    .find_iter(head)
+    .find(|link| START_REL.is_match(link.as_str()))
+    .and_then(|link| HREF_ATTR.captures(link.as_str()))
+    .map(|caps| caps[1].to_string())
+}
+
+/// The (prev, next) neighbours of a (version-less) paper id, from the
+/// `paper_order` hash -- `None` for both unless the cache is Redis, the only
+/// backend holding it.
+pub async fn adjacent_papers(
+  cache_opt: Option<&PaperCache>,
+  id_arxiv: &str,
+) -> (Option<String>, Option<String>) {
+  let mut pieces: Vec<String> = if let Some(cache) = cache_opt {
+    if let Ok(adjacent_papers) = hget_cached(cache, "paper_order", id_arxiv).await {
       adjacent_papers.split(';').map(|x| x.to_string()).collect()
     } else {
       Vec::new()
@@ -127,143 +444,150 @@ pub async fn assemble_paper(
   } else {
     Some(pieces.pop().unwrap())
   };
-  // Build a single coherent HTML page -- also off the async workers,
-  // since the regex branding pass is CPU-bound.
-  let id_arxiv_branding = id_arxiv.clone();
-  let status_branding = status.clone();
-  let branded_html = spawn_blocking(move || {
-    dirty_branded_ar5iv_html(html, &id_arxiv_branding, status_branding, prev, next)
-  })
-  .await
-  .ok()?;
-  // Cache the paper itself, so the next request is a fast Redis hit.
-  if branded_html.len() <= TEN_MIB {
-    // cap cache items at 10 MiB
-    if let Some(ref mut conn) = conn_opt {
-      set_cached(&mut *conn, &paper_key(&id_arxiv), branded_html.as_str())
-        .await
-        .ok();
-    }
-  }
-  // Warm the asset and log caches in a detached task, off this request's
-  // critical path -- the browser will start fetching the assets as soon as
-  // it receives the HTML we are about to return.
-  if let Some(mut conn) = conn_opt {
-    rocket::tokio::spawn(async move {
-      for (name, val) in assets.into_iter() {
-        if val.len() <= TEN_MIB {
-          // cap cache items at 10 MiB
-          let cache_key = asset_key(&id_arxiv, &name);
-          set_cached_asset(&mut conn, cache_key.as_str(), &val).await.ok();
-        }
-      }
-      if !log.is_empty() && log.len() <= TEN_MIB {
-        let html_log = log_to_html(&log, &id_arxiv);
-        set_cached(&mut conn, &log_key(&id_arxiv), &html_log).await.ok();
-      }
-    });
-  }
-  Some(branded_html)
+  (prev, next)
 }
 
+/// An asset of a paper's bundle: read into memory, or -- when larger than
+/// `SIXTY_FOUR_MIB`, or stored uncompressed and too large to cache -- left in
+/// the ZIP, to be served from it. (Its `head` is read all the same, to sniff
+/// its content type.)
+#[derive(Clone)]
+pub enum PaperAsset {
+  Buffered(SharedBytes),
+  Oversized {
+    bundle: PathBuf,
+    len: u64,
+    head: Vec<u8>,
+  },
+}
+
+/// An asset of a paper's bundle, with the validators of its ZIP entry.
 pub async fn assemble_paper_asset(
   field_opt: Option<&str>,
   id: &str,
   filename: &str,
-) -> Option<Vec<u8>> {
-  let paper_path = build_paper_path(field_opt, id)?;
+) -> Result<(PaperAsset, Validators), AssembleError> {
+  let paper_path = build_paper_path(field_opt, id)?.path;
   let filename = filename.to_string();
-  spawn_blocking(move || {
-    let zipf = File::open(paper_path).ok()?;
-    let reader = BufReader::new(zipf);
-    let mut zip = ZipArchive::new(reader).ok()?;
-    let mut asset = zip.by_name(&filename).ok()?;
-    // refuse to buffer pathologically large assets into RAM
-    if asset.size() > SIXTY_FOUR_MIB {
-      return None;
+  spawn_blocking(move || -> Result<_, AssembleError> {
+    // seeks straight to the entry, with the bundle's index
+    let (zipf, index) = ZIP_INDEXES.open(&paper_path)?;
+    let bundle = zipf.metadata()?;
+    let asset = index.entry(&filename).ok_or(AssembleError::NotFound)?;
+    let validators = Validators::of_bundle_entry(&bundle, asset.crc32);
+    // don't buffer pathologically large assets into RAM, nor stored ones that
+    // can't be cached: those are served from the bundle as they are
+    let stored_uncached = asset.is_stored() && asset.size > TEN_MIB as u64;
+    if asset.size > SIXTY_FOUR_MIB || stored_uncached {
+      let cap = if stored_uncached {
+        "ten_mib"
+      } else {
+        "sixty_four_mib"
+      };
+      METRICS.inc("ar5iv_skipped_assets_total", &[("cap", cap)]);
+      let mut head = Vec::with_capacity(SNIFF_LEN);
+      asset
+        .reader(&zipf, 0)?
+        .take(SNIFF_LEN as u64)
+        .read_to_end(&mut head)?;
+      return Ok((
+        PaperAsset::Oversized {
+          bundle: paper_path,
+          len: asset.size,
+          head,
+        },
+        validators,
+      ));
     }
-    let mut file_contents = Vec::with_capacity(asset.size() as usize);
-    asset.read_to_end(&mut file_contents).ok()?;
-    Some(file_contents)
+    Ok((
+      PaperAsset::Buffered(SharedBytes::from(asset.read(&zipf)?)),
+      validators,
+    ))
   })
-  .await
-  .ok()
-  .flatten()
+  .await?
 }
 
-pub async fn fetch_zip(field_opt: Option<&str>, id: &str) -> Option<NamedFile> {
-  if let Some(paper_path) = build_source_zip_path(field_opt, id) {
-    // stream the ZIP from disk instead of buffering it into RAM;
-    // NamedFile also derives the application/zip content type from the extension.
-    NamedFile::open(paper_path).await.ok()
-  } else {
-    None
-  }
+pub async fn fetch_zip(field_opt: Option<&str>, id: &str) -> Option<Ranged> {
+  let paper_path = build_source_zip_path(field_opt, id)?;
+  // stream the ZIP from disk instead of buffering it into RAM
+  let zipf = AsyncFile::open(paper_path).await.ok()?.into_std().await;
+  let metadata = zipf.metadata().ok()?;
+  Some(Ranged::file(ContentType::ZIP, zipf, &metadata))
 }
 
-pub async fn assemble_log(field_opt: Option<&str>, id: &str) -> Option<String> {
-  let paper_path = build_paper_path(field_opt, id)?;
-  let id_arxiv = build_arxiv_id(&field_opt, id);
-  spawn_blocking(move || {
-    let zipf = File::open(paper_path).ok()?;
-    let reader = BufReader::new(zipf);
-    let mut zip = ZipArchive::new(reader).ok()?;
-    let mut asset = zip.by_name(LOG_FILENAME).ok()?;
-    let mut conversion_report = String::new();
-    asset.read_to_string(&mut conversion_report).ok()?;
-    Some(log_to_html(&conversion_report, &id_arxiv))
+pub async fn assemble_log(field_opt: Option<&str>, id: &str) -> Result<String, AssembleError> {
+  let bundle = build_paper_path(field_opt, id)?;
+  let paper_path = bundle.path;
+  let id_arxiv = build_arxiv_id(&field_opt, &bundle.id);
+  spawn_blocking(move || -> Result<_, AssembleError> {
+    let conversion_report = read_bundle_log(&paper_path)?;
+    Ok(log_to_html(
+      &ConversionLog::parse(&conversion_report),
+      &id_arxiv,
+    ))
   })
-  .await
-  .ok()
-  .flatten()
+  .await?
 }
 
-fn log_to_status(log: &str) -> LatexmlStatus {
-  let mut status = LatexmlStatus::Ok;
-  for line in log.lines() {
-    if line.starts_with("Warning:") && status < LatexmlStatus::Warning {
-      status = LatexmlStatus::Warning;
-    } else if line.starts_with("Error:") && status < LatexmlStatus::Error {
-      status = LatexmlStatus::Error;
-    } else if line.starts_with("Fatal:") && status < LatexmlStatus::Fatal {
-      status = LatexmlStatus::Fatal;
-    } else if line.starts_with("Status:conversion:") {
-      // (a status line truncated right after the prefix counts as fatal)
-      match line.chars().nth(18) {
-        Some('0') => {
-          if status <= LatexmlStatus::Ok {
-            status = LatexmlStatus::Ok
-          }
-        }
-        Some('1') => {
-          if status < LatexmlStatus::Warning {
-            status = LatexmlStatus::Warning
-          }
-        }
-        Some('2') => {
-          if status < LatexmlStatus::Error {
-            status = LatexmlStatus::Error
-          }
-        }
-        _ => status = LatexmlStatus::Fatal,
-      }
+/// The conversion log of a paper, parsed into typed entries.
+pub async fn assemble_log_report(
+  field_opt: Option<&str>,
+  id: &str,
+) -> Result<ConversionLog, AssembleError> {
+  let paper_path = build_paper_path(field_opt, id)?.path;
+  spawn_blocking(move || -> Result<_, AssembleError> {
+    let conversion_report = read_bundle_log(&paper_path)?;
+    Ok(ConversionLog::parse(&conversion_report))
+  })
+  .await?
+}
+
+/// Reads the raw conversion log out of a result bundle (blocking).
+pub fn read_bundle_log(paper_path: &Path) -> Result<String, AssembleError> {
+  let zipf = File::open(paper_path)?;
+  let reader = BufReader::new(zipf);
+  let mut zip = ZipArchive::new(reader)?;
+  let mut asset = zip.by_name(LOG_FILENAME)?;
+  AssembleError::read_text(&mut asset, LOG_FILENAME)
+}
+
+pub fn log_to_status(log: &str) -> LatexmlStatus {
+  ConversionLog::parse(log).status()
+}
+
+/// Resolves the result bundle to serve for an id. A versioned id (e.g.
+/// "2105.04404v3") is served from its version-specific bundle
+/// (`2105.04404v3/tex_to_html.zip`) when we hold one, and otherwise falls back
+/// to the default bundle of the version-less id, noting the missing version.
+pub fn build_paper_path(field_opt: Option<&str>, id: &str) -> Result<PaperBundle, AssembleError> {
+  let (id_base, version_opt) = split_arxiv_version(id);
+  if version_opt.is_some() {
+    if let Some(path) = build_bundle_path(field_opt, id) {
+      return Ok(PaperBundle {
+        path,
+        kind: BundleKind::of(id),
+        id: id.to_string(),
+        missing_version: None,
+      });
     }
   }
-  status
+  let path = build_bundle_path(field_opt, id_base).ok_or(AssembleError::NotFound)?;
+  Ok(PaperBundle {
+    path,
+    kind: BundleKind::of(id_base),
+    id: id_base.to_string(),
+    missing_version: version_opt.map(str::to_string),
+  })
 }
 
-fn build_paper_path(field_opt: Option<&str>, id: &str) -> Option<PathBuf> {
+fn build_bundle_path(field_opt: Option<&str>, id: &str) -> Option<PathBuf> {
   // basic sanity: valid ids start with at least 4 characters (e.g. "YYMM").
   // `get` returns None -- rather than panicking -- for short ids, and for ids
   // where byte 4 would split a multi-byte UTF-8 character.
   let id_base = id.get(0..4)?;
   // latexml-oxide months (2606. and on) ship `oxidized_tex_to_html.zip`; older
   // months keep the legacy `tex_to_html.zip`.
-  let bundle = if uses_oxidized_bundle(id) {
-    "oxidized_tex_to_html.zip"
-  } else {
-    "tex_to_html.zip"
-  };
+  let bundle = BundleKind::of(id).file_name();
   let paper_path_str = format!(
     "{}/{}/{}{}/{}",
     *AR5IV_PAPERS_ROOT_DIR,
@@ -324,9 +648,51 @@ mod tests {
   #[test]
   fn short_and_multibyte_ids_build_no_path() {
     // regression: `&id[0..4]` used to panic on both of these
-    assert_eq!(build_paper_path(None, "abc"), None);
+    assert_eq!(build_paper_path(None, "abc"), Err(AssembleError::NotFound));
     assert_eq!(build_source_zip_path(None, "abc"), None);
-    assert_eq!(build_paper_path(None, "ab€cd"), None);
+    assert_eq!(
+      build_paper_path(None, "ab€cd"),
+      Err(AssembleError::NotFound)
+    );
     assert_eq!(build_source_zip_path(None, "ab€cd"), None);
+    assert_eq!(
+      build_paper_path(None, "abcv2"),
+      Err(AssembleError::NotFound)
+    );
+  }
+
+  fn bundle_with(pages: &[(&str, &str)]) -> ZipArchive<std::io::Cursor<Vec<u8>>> {
+    use std::io::Write;
+    let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    for (name, html) in pages {
+      zip
+        .start_file(*name, zip::write::SimpleFileOptions::default())
+        .unwrap();
+      zip.write_all(html.as_bytes()).unwrap();
+    }
+    ZipArchive::new(zip.finish().unwrap()).unwrap()
+  }
+
+  #[test]
+  fn main_page_is_picked_by_index_or_start_link() {
+    let chapter = r#"<html><head><link rel="start" href="thesis.html" title="A thesis"></head>"#;
+    let mut zip = bundle_with(&[
+      ("Ch2.html", chapter),
+      ("thesis.html", "<html><head></head></html>"),
+      ("Ch1.html", chapter),
+      ("x1.png", "not a page"),
+      ("figures/plot.html", "<html></html>"),
+    ]);
+    assert_eq!(
+      bundle_pages(&mut zip),
+      ["thesis.html", "Ch1.html", "Ch2.html"]
+    );
+
+    let mut zip = bundle_with(&[("Ch1.html", chapter), ("index.html", "<html></html>")]);
+    assert_eq!(bundle_pages(&mut zip), ["index.html", "Ch1.html"]);
+
+    // without any hints, by name rather than by ZIP order
+    let mut zip = bundle_with(&[("b.html", "<html></html>"), ("a.html", "<html></html>")]);
+    assert_eq!(bundle_pages(&mut zip), ["a.html", "b.html"]);
   }
 }
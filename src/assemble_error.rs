@@ -0,0 +1,178 @@
+use rocket::error;
+use rocket::http::Status;
+use rocket::request::Request;
+use rocket::response::{self, Responder};
+use rocket::serde::json::json;
+use rocket::tokio::task::JoinError;
+use rocket_dyn_templates::Template;
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use zip::result::ZipError;
+
+use crate::access_log::RequestLog;
+use crate::assembly_budget::Overloaded;
+use crate::constants::{AR5IV_CSS_URL, AR5IV_FONTS_CSS_URL, SITE_CSS_URL};
+use crate::metrics::METRICS;
+
+/// Why a paper, page, log or asset could not be assembled from its bundle.
+/// Only `NotFound` means we don't hold it (and /html/ may send the reader to
+/// arxiv.org); the others are ours to fix, and answered with a 500 -- or a 503
+/// for `Overloaded`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AssembleError {
+  /// No bundle for the id, or no such page, asset or log in it.
+  NotFound,
+  /// The bundle could not be read, e.g. when replaced mid-read by a data
+  /// update.
+  Io(String),
+  /// The bundle is not a valid ZIP, or an entry of it fails to decompress.
+  Corrupt(String),
+  /// A page or log of the bundle that is not UTF-8.
+  NotUtf8(String),
+  /// A page whose markup the branding rewriter gave up on.
+  Branding(String),
+  /// The blocking task assembling it panicked.
+  Panicked(String),
+  /// Turned away while the assembly budget is exhausted.
+  Overloaded(Overloaded),
+}
+
+impl AssembleError {
+  /// The label of the error in metrics and logs.
+  pub fn kind(&self) -> &'static str {
+    match self {
+      AssembleError::NotFound => "not_found",
+      AssembleError::Io(_) => "io",
+      AssembleError::Corrupt(_) => "corrupt",
+      AssembleError::NotUtf8(_) => "not_utf8",
+      AssembleError::Branding(_) => "branding",
+      AssembleError::Panicked(_) => "panicked",
+      AssembleError::Overloaded(_) => "overloaded",
+    }
+  }
+
+  /// A page or log entry of a bundle, as text.
+  pub fn read_text(entry: &mut impl io::Read, name: &str) -> Result<String, AssembleError> {
+    let mut bytes = Vec::new();
+    entry.read_to_end(&mut bytes)?;
+    String::from_utf8(bytes).map_err(|_| AssembleError::NotUtf8(name.to_string()))
+  }
+}
+
+impl fmt::Display for AssembleError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      AssembleError::NotFound => write!(f, "no such document"),
+      AssembleError::Io(e) => write!(f, "the conversion bundle could not be read ({e})"),
+      AssembleError::Corrupt(e) => write!(f, "the conversion bundle is damaged ({e})"),
+      AssembleError::NotUtf8(name) => write!(f, "{name} in the conversion bundle is not UTF-8"),
+      AssembleError::Branding(e) => write!(f, "the document could not be branded ({e})"),
+      AssembleError::Panicked(e) => write!(f, "assembling the document failed ({e})"),
+      AssembleError::Overloaded(_) => write!(f, "busy assembling other papers"),
+    }
+  }
+}
+
+impl std::error::Error for AssembleError {}
+
+impl From<ZipError> for AssembleError {
+  fn from(e: ZipError) -> Self {
+    match e {
+      ZipError::FileNotFound => AssembleError::NotFound,
+      ZipError::Io(e) => AssembleError::from(e),
+      e => AssembleError::Corrupt(e.to_string()),
+    }
+  }
+}
+
+impl From<io::Error> for AssembleError {
+  fn from(e: io::Error) -> Self {
+    match e.kind() {
+      // (decompression and checksum failures surface as these)
+      io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof => {
+        AssembleError::Corrupt(e.to_string())
+      }
+      _ => AssembleError::Io(e.to_string()),
+    }
+  }
+}
+
+impl From<JoinError> for AssembleError {
+  fn from(e: JoinError) -> Self {
+    AssembleError::Panicked(e.to_string())
+  }
+}
+
+impl From<Overloaded> for AssembleError {
+  fn from(overloaded: Overloaded) -> Self {
+    AssembleError::Overloaded(overloaded)
+  }
+}
+
+/// A missing document is left to the 404 catcher; a broken one is logged,
+/// counted, and answered with the 500 page, naming the request id to report
+/// -- or, for the `/api/` routes, with a JSON error.
+impl<'r> Responder<'r, 'static> for AssembleError {
+  fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+    if let AssembleError::NotFound = self {
+      return Err(Status::NotFound);
+    }
+    METRICS.inc("ar5iv_assembly_errors_total", &[("kind", self.kind())]);
+    let log = req.local_cache(RequestLog::default);
+    log.error(&self);
+    if let AssembleError::Overloaded(overloaded) = self {
+      return overloaded.respond_to(req);
+    }
+    let path = req.uri().path().to_string();
+    error!("cannot assemble {path}: {self}");
+    if path.starts_with("/api/") {
+      let body = json!({
+        "error": self.to_string(),
+        "kind": self.kind(),
+        "path": path,
+        "request_id": log.request_id(),
+      });
+      return (Status::InternalServerError, body).respond_to(req);
+    }
+    let mut map = HashMap::new();
+    map.insert("AR5IV_FONTS_CSS_URL", AR5IV_FONTS_CSS_URL.to_string());
+    map.insert("AR5IV_CSS_URL", AR5IV_CSS_URL.to_string());
+    map.insert("SITE_CSS_URL", SITE_CSS_URL.to_string());
+    map.insert("id", path.trim_start_matches('/').to_string());
+    map.insert("problem", self.to_string());
+    map.insert("request_id", log.request_id());
+    let mut response = Template::render("500", &map).respond_to(req)?;
+    response.set_status(Status::InternalServerError);
+    Ok(response)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn only_missing_entries_are_not_found() {
+    assert_eq!(
+      AssembleError::from(ZipError::FileNotFound),
+      AssembleError::NotFound
+    );
+    assert_eq!(
+      AssembleError::from(ZipError::InvalidArchive("bad EOCD".into())).kind(),
+      "corrupt"
+    );
+    assert_eq!(
+      AssembleError::from(io::Error::from(io::ErrorKind::PermissionDenied)).kind(),
+      "io"
+    );
+    assert_eq!(
+      AssembleError::read_text(&mut &b"\xff\xfe"[..], "paper.html"),
+      Err(AssembleError::NotUtf8(String::from("paper.html")))
+    );
+    assert_eq!(
+      AssembleError::read_text(&mut &b"<html>"[..], "paper.html").unwrap(),
+      "<html>"
+    );
+  }
+}
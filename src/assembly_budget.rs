@@ -0,0 +1,219 @@
+use rocket::fairing::AdHoc;
+use rocket::figment::Figment;
+use rocket::http::Status;
+use rocket::request::Request;
+use rocket::response::{self, Responder, Response};
+use rocket::serde::Deserialize;
+use rocket::tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use rocket::tokio::task::spawn_blocking;
+use rocket::warn;
+use std::io::Cursor;
+use std::path::PathBuf;
+use std::sync::{Arc, OnceLock};
+
+use crate::zip_index::ZIP_INDEXES;
+
+/// The budget is kept in KiB permits: a semaphore counts at most `u32` of
+/// them per acquisition.
+const UNIT: u64 = 1024;
+
+static BUDGET: OnceLock<AssemblyBudget> = OnceLock::new();
+
+/// The `[assembly]` table of Rocket.toml: how much memory the cache misses
+/// being assembled may take together (0: no limit), and the `Retry-After` (in
+/// seconds) of the requests turned away beyond it.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct AssemblyConfig {
+  pub budget_bytes: u64,
+  pub retry_after: u64,
+}
+
+impl Default for AssemblyConfig {
+  fn default() -> Self {
+    AssemblyConfig {
+      budget_bytes: 2_147_483_648,
+      retry_after: 5,
+    }
+  }
+}
+
+/// The memory available to cache-miss assembly, estimated from the
+/// uncompressed sizes in the bundles: a burst of misses beyond it is shed
+/// with a 503, rather than queueing decompressions until the service runs out
+/// of memory. Cache hits never touch it.
+pub struct AssemblyBudget {
+  config: AssemblyConfig,
+  permits: Option<Arc<Semaphore>>,
+  units: u32,
+}
+
+/// A share of the budget, given back when dropped.
+#[derive(Debug, Default)]
+pub struct AssemblyPermit {
+  /// (held only to be dropped)
+  _permit: Option<OwnedSemaphorePermit>,
+}
+
+/// A cache miss turned away while the budget is exhausted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Overloaded {
+  pub retry_after: u64,
+}
+
+impl AssemblyBudget {
+  pub fn new(config: AssemblyConfig) -> Self {
+    let units = (config.budget_bytes / UNIT).min(u32::MAX as u64) as u32;
+    let permits = (units > 0).then(|| Arc::new(Semaphore::new(units as usize)));
+    AssemblyBudget {
+      config,
+      permits,
+      units,
+    }
+  }
+
+  /// The budget in effect: Rocket.toml's, once the fairing has read it.
+  pub fn get() -> &'static AssemblyBudget {
+    BUDGET.get_or_init(|| AssemblyBudget::new(AssemblyConfig::default()))
+  }
+
+  /// Puts the `[assembly]` table of a configuration in effect (unless one
+  /// already is).
+  pub fn load(figment: &Figment) -> &'static AssemblyBudget {
+    BUDGET.get_or_init(|| {
+      let config = match figment.extract_inner::<AssemblyConfig>("assembly") {
+        Ok(config) => config,
+        Err(e) if e.missing() => AssemblyConfig::default(),
+        Err(e) => {
+          warn!("invalid [assembly] configuration, using the defaults: {e}");
+          AssemblyConfig::default()
+        }
+      };
+      AssemblyBudget::new(config)
+    })
+  }
+
+  pub fn fairing() -> AdHoc {
+    AdHoc::on_ignite("Assembly budget", |rocket| async {
+      AssemblyBudget::load(rocket.figment());
+      rocket
+    })
+  }
+
+  /// Reserves `bytes` of the budget, without waiting: when they are not
+  /// available, the caller is to come back later. (An estimate above the
+  /// whole budget takes all of it, and so runs alone.)
+  pub fn try_reserve(&self, bytes: u64) -> Result<AssemblyPermit, Overloaded> {
+    let Some(ref permits) = self.permits else {
+      return Ok(AssemblyPermit::default());
+    };
+    let units = bytes.div_ceil(UNIT).clamp(1, self.units as u64) as u32;
+    match permits.clone().try_acquire_many_owned(units) {
+      Ok(permit) => Ok(AssemblyPermit {
+        _permit: Some(permit),
+      }),
+      Err(_) => Err(Overloaded {
+        retry_after: self.config.retry_after,
+      }),
+    }
+  }
+
+  /// Reserves what assembling a bundle is estimated to take: the uncompressed
+  /// size of `entry` in it, or of all its entries.
+  pub async fn reserve_for(
+    &self,
+    bundle: PathBuf,
+    entry: Option<String>,
+  ) -> Result<AssemblyPermit, Overloaded> {
+    if self.permits.is_none() {
+      return Ok(AssemblyPermit::default());
+    }
+    // (opening the bundle is I/O, and so may be reading its central
+    // directory; an unreadable bundle reserves next to nothing, and fails its
+    // assembly all the same)
+    let bytes = spawn_blocking(move || {
+      let (_, index) = ZIP_INDEXES.open(&bundle).ok()?;
+      Some(index.uncompressed_size(entry.as_deref()))
+    })
+    .await
+    .ok()
+    .flatten()
+    .unwrap_or_default();
+    self.try_reserve(bytes)
+  }
+
+  pub fn budget_bytes(&self) -> u64 {
+    self.units as u64 * UNIT
+  }
+
+  /// The part of the budget taken by the assemblies in flight.
+  pub fn reserved_bytes(&self) -> u64 {
+    self.permits.as_ref().map_or(0, |permits| {
+      (self.units as u64 - permits.available_permits() as u64) * UNIT
+    })
+  }
+}
+
+impl<'r> Responder<'r, 'static> for Overloaded {
+  fn respond_to(self, _req: &'r Request<'_>) -> response::Result<'static> {
+    let body = format!(
+      "ar5iv is busy assembling other papers, please retry in {} seconds.",
+      self.retry_after
+    );
+    Response::build()
+      .status(Status::ServiceUnavailable)
+      .raw_header("Retry-After", self.retry_after.to_string())
+      .sized_body(body.len(), Cursor::new(body))
+      .ok()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::zip_index::ZipIndex;
+  use std::io::Write;
+
+  #[test]
+  fn reservations_beyond_the_budget_are_shed() {
+    let budget = AssemblyBudget::new(AssemblyConfig {
+      budget_bytes: 10 * UNIT,
+      retry_after: 7,
+    });
+    let first = budget.try_reserve(6 * UNIT).unwrap();
+    assert_eq!(budget.reserved_bytes(), 6 * UNIT);
+    assert_eq!(
+      budget.try_reserve(5 * UNIT).unwrap_err(),
+      Overloaded { retry_after: 7 }
+    );
+    // (a tiny miss still fits)
+    let second = budget.try_reserve(1).unwrap();
+    drop(first);
+    drop(second);
+    assert_eq!(budget.reserved_bytes(), 0);
+    // an oversized one runs, alone
+    let whole = budget.try_reserve(100 * UNIT).unwrap();
+    assert!(budget.try_reserve(1).is_err());
+    drop(whole);
+
+    let unlimited = AssemblyBudget::new(AssemblyConfig {
+      budget_bytes: 0,
+      retry_after: 7,
+    });
+    assert!(unlimited.try_reserve(u64::MAX).is_ok());
+  }
+
+  #[test]
+  fn estimates_come_from_the_central_directory() {
+    let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+    let options = zip::write::SimpleFileOptions::default();
+    zip.start_file("paper.html", options).unwrap();
+    zip.write_all(&[b'x'; 3000]).unwrap();
+    zip.start_file("x1.png", options).unwrap();
+    zip.write_all(&[0; 500]).unwrap();
+    let index = ZipIndex::read(zip.finish().unwrap(), None, 0).unwrap();
+    assert_eq!(index.uncompressed_size(None), 3500);
+    assert_eq!(index.uncompressed_size(Some("x1.png")), 500);
+    assert_eq!(index.uncompressed_size(Some("missing.png")), 0);
+  }
+}
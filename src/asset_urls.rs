@@ -0,0 +1,239 @@
+use lol_html::html_content::Element;
+use lol_html::HandlerResult;
+use regex::{Captures, Regex};
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::sync::LazyLock;
+
+static URL_SCHEME: LazyLock<Regex> =
+  LazyLock::new(|| Regex::new(r"^[A-Za-z][A-Za-z0-9+.\-]*:").unwrap());
+/// `url(x.png)`, `url('x.png')`, `url("x.png")` and, inside a double-quoted
+/// attribute, `url(&quot;x.png&quot;)`.
+static CSS_URL: LazyLock<Regex> = LazyLock::new(|| {
+  Regex::new(r#"url\(\s*(?:'([^']*)'|&quot;(.*?)&quot;|"([^"]*)"|([^)'"\s]*))\s*\)"#).unwrap()
+});
+
+/// The attributes holding a single URL that may point into a paper's bundle.
+const URL_ATTRIBUTES: [&str; 4] = ["src", "href", "poster", "data"];
+
+/// Points the relative URLs of a latexml document at its bundle, served under
+/// `/html/<id>/assets/`, and at its sibling pages, served under `/html/<id>`.
+///
+/// Article pages live at `/html/<id>`, so a relative `x1.png` would resolve
+/// to `/html/x1.png` -- and for the old ID scheme (`astro-ph/0001016`) to
+/// somewhere else entirely. Rather than struggle with relativistic issues,
+/// every reference to the bundle is made absolute. `data:` and remote URLs,
+/// absolute paths and in-page `#fragments` are left as they are.
+pub struct AssetUrls {
+  page_prefix: String,
+  prefix: String,
+  main_page: Option<String>,
+  pages: HashSet<String>,
+}
+
+impl AssetUrls {
+  pub fn new(id_arxiv: &str) -> Self {
+    AssetUrls {
+      page_prefix: format!("/html/{id_arxiv}"),
+      prefix: format!("/html/{id_arxiv}/assets/"),
+      main_page: None,
+      pages: HashSet::new(),
+    }
+  }
+
+  /// Also points links between the HTML pages of a multi-page conversion at
+  /// their routes: the main page (listed first) at `/html/<id>`, the others
+  /// at `/html/<id>/<page>.html`.
+  pub fn with_pages(mut self, pages: &[String]) -> Self {
+    if let Some((main_page, others)) = pages.split_first() {
+      self.main_page = Some(main_page.clone());
+      self.pages = others.iter().cloned().collect();
+    }
+    self
+  }
+
+  /// The absolute URL of a reference into the bundle, if it is one.
+  pub fn rewrite<'a>(&self, url: &'a str) -> Cow<'a, str> {
+    if !is_bundle_relative(url) {
+      return Cow::Borrowed(url);
+    }
+    let (path, suffix) = url.split_at(url.find(['#', '?']).unwrap_or(url.len()));
+    if self.main_page.as_deref() == Some(path) {
+      Cow::Owned(self.page_prefix.clone() + suffix)
+    } else if self.pages.contains(path) {
+      Cow::Owned(self.page_prefix.clone() + "/" + url)
+    } else {
+      Cow::Owned(self.prefix.clone() + url)
+    }
+  }
+
+  /// Rewrites each candidate of a `srcset` list, e.g. `x1.png 1x, x1@2x.png 2x`,
+  /// keeping descriptors and separators as they are.
+  pub fn rewrite_srcset(&self, srcset: &str) -> String {
+    let mut rewritten = String::with_capacity(srcset.len() + 2 * self.prefix.len());
+    let mut rest = srcset;
+    loop {
+      let separators = rest.len()
+        - rest
+          .trim_start_matches(|c: char| c.is_whitespace() || c == ',')
+          .len();
+      rewritten.push_str(&rest[..separators]);
+      rest = &rest[separators..];
+      if rest.is_empty() {
+        break;
+      }
+      // a candidate's URL runs up to whitespace, trailing commas excluded
+      // (data: URLs have commas of their own)
+      let url_end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+      let url = rest[..url_end].trim_end_matches(',');
+      rewritten.push_str(&self.rewrite(url));
+      rest = &rest[url.len()..];
+      if url.len() == url_end {
+        let descriptors_end = rest.find(',').unwrap_or(rest.len());
+        rewritten.push_str(&rest[..descriptors_end]);
+        rest = &rest[descriptors_end..];
+      }
+    }
+    rewritten
+  }
+
+  /// Rewrites the `url(...)` references of inline CSS, as in
+  /// `style="background:url(x1.png)"`.
+  pub fn rewrite_css<'a>(&self, css: &'a str) -> Cow<'a, str> {
+    CSS_URL.replace_all(css, |caps: &Captures| {
+      let whole = caps.get(0).unwrap();
+      match (1..=4).find_map(|i| caps.get(i)) {
+        Some(url) => {
+          let (start, end) = (url.start() - whole.start(), url.end() - whole.start());
+          String::from(&whole.as_str()[..start])
+            + &self.rewrite(url.as_str())
+            + &whole.as_str()[end..]
+        }
+        None => whole.as_str().to_string(),
+      }
+    })
+  }
+
+  /// Rewrites every asset-bearing attribute of an element. The `href` of a
+  /// `<link>` is not one: the bundle's `LaTeXML.css` is not to load next to
+  /// ar5iv's stylesheets.
+  pub fn rewrite_element(&self, el: &mut Element) -> HandlerResult {
+    for name in URL_ATTRIBUTES {
+      if name == "href" && el.tag_name() == "link" {
+        continue;
+      }
+      if let Some(url) = el.get_attribute(name) {
+        if let Cow::Owned(rewritten) = self.rewrite(&url) {
+          el.set_attribute(name, &rewritten)?;
+        }
+      }
+    }
+    if let Some(srcset) = el.get_attribute("srcset") {
+      let rewritten = self.rewrite_srcset(&srcset);
+      if rewritten != srcset {
+        el.set_attribute("srcset", &rewritten)?;
+      }
+    }
+    if let Some(style) = el.get_attribute("style") {
+      let rewritten = self.rewrite_css(&style);
+      if rewritten != style {
+        el.set_attribute("style", &rewritten)?;
+      }
+    }
+    Ok(())
+  }
+}
+
+/// Whether a URL is a relative reference, i.e. points inside the paper's
+/// bundle: not empty, and neither a `scheme:` URL (`data:`, `http(s):`,
+/// `mailto:`, ...), an absolute or protocol-relative path, a query nor a
+/// fragment.
+pub fn is_bundle_relative(url: &str) -> bool {
+  !url.is_empty()
+    && !url.starts_with(['/', '#', '?'])
+    && !url.starts_with(char::is_whitespace)
+    && !URL_SCHEME.is_match(url)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn urls() -> AssetUrls {
+    AssetUrls::new("2105.04404")
+  }
+
+  #[test]
+  fn only_relative_urls_point_into_the_bundle() {
+    let urls = urls();
+    assert_eq!(urls.rewrite("x1.png"), "/html/2105.04404/assets/x1.png");
+    assert_eq!(
+      urls.rewrite("figures/x1.png"),
+      "/html/2105.04404/assets/figures/x1.png"
+    );
+    for kept in [
+      "",
+      "data:image/png;base64,AAAA",
+      "http://example.org/x.png",
+      "https://example.org/x.png",
+      "mailto:someone@example.org",
+      "/assets/ar5iv.png",
+      "//example.org/x.png",
+      "#S1.F1",
+      "?page=2",
+    ] {
+      assert_eq!(urls.rewrite(kept), kept);
+    }
+  }
+
+  #[test]
+  fn page_links_point_at_page_routes() {
+    let urls = AssetUrls::new("2105.04404")
+      .with_pages(&[String::from("main.html"), String::from("Ch1.html")]);
+    assert_eq!(urls.rewrite("main.html"), "/html/2105.04404");
+    assert_eq!(urls.rewrite("main.html#S2"), "/html/2105.04404#S2");
+    assert_eq!(
+      urls.rewrite("Ch1.html#S1.F1"),
+      "/html/2105.04404/Ch1.html#S1.F1"
+    );
+    assert_eq!(urls.rewrite("Ch2.html"), "/html/2105.04404/assets/Ch2.html");
+    assert_eq!(urls.rewrite("x1.png"), "/html/2105.04404/assets/x1.png");
+  }
+
+  #[test]
+  fn srcset_candidates_are_rewritten() {
+    let urls = urls();
+    assert_eq!(
+      urls.rewrite_srcset("x1.png 1x, x1@2x.png 2x"),
+      "/html/2105.04404/assets/x1.png 1x, /html/2105.04404/assets/x1@2x.png 2x"
+    );
+    assert_eq!(
+      urls.rewrite_srcset("x1.png 320w,https://example.org/x2.png 640w"),
+      "/html/2105.04404/assets/x1.png 320w,https://example.org/x2.png 640w"
+    );
+    assert_eq!(
+      urls.rewrite_srcset("data:image/png;base64,AA== 1x, x1.png 2x"),
+      "data:image/png;base64,AA== 1x, /html/2105.04404/assets/x1.png 2x"
+    );
+  }
+
+  #[test]
+  fn css_urls_are_rewritten() {
+    let urls = urls();
+    assert_eq!(
+      urls.rewrite_css("background:url(x1.png) no-repeat"),
+      "background:url(/html/2105.04404/assets/x1.png) no-repeat"
+    );
+    assert_eq!(
+      urls.rewrite_css("background-image: url( 'x1.png' )"),
+      "background-image: url( '/html/2105.04404/assets/x1.png' )"
+    );
+    assert_eq!(
+      urls.rewrite_css("background:url(&quot;x1.png&quot;)"),
+      "background:url(&quot;/html/2105.04404/assets/x1.png&quot;)"
+    );
+    let remote = "background:url(https://example.org/x.png);color:red";
+    assert_eq!(urls.rewrite_css(remote), remote);
+    assert_eq!(urls.rewrite_css("color:red"), "color:red");
+  }
+}
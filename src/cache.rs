@@ -1,27 +1,72 @@
-use crate::assemble_asset::{assemble_log, assemble_paper, assemble_paper_asset};
+use crate::access_log::RequestLog;
+use crate::assemble_asset::{
+  assemble_log, assemble_log_report, assemble_paper, assemble_paper_asset, assemble_paper_page,
+  assemble_paper_warming, build_paper_path, PaperAsset,
+};
+use crate::assemble_error::AssembleError;
+use crate::assembly_budget::{AssemblyBudget, AssemblyPermit};
+use crate::cache_backend::{
+  CacheError, CacheTier, CachedEntry, DiskCache, MemoryCache, PaperCache, RedisCache, SharedBytes,
+};
+use crate::conditional::{Ranged, Validators};
+use crate::constants::LOG_FILENAME;
+use crate::conversion_log::ConversionLog;
+use crate::encoding::{AcceptEncoding, EncodedBody, Encoding};
+use crate::metadata::{assemble_paper_metadata, PaperMetadata};
+use crate::metrics::METRICS;
+use crate::single_flight::SingleFlight;
+use crate::sniff::{asset_content_type, is_image_request};
+use crate::zip_index::ZIP_INDEXES;
 use rand::seq::SliceRandom;
 use regex::Regex;
+use rocket::fairing::AdHoc;
+use rocket::figment::Figment;
 use rocket::fs::NamedFile;
-use rocket::http::ContentType;
+use rocket::serde::Deserialize;
+use rocket::tokio::fs::File as AsyncFile;
+use rocket::tokio::io::AsyncReadExt;
 use rocket::tokio::sync::Mutex;
+use rocket::tokio::task::spawn_blocking;
+use rocket::{warn, Responder};
 use rocket_db_pools::deadpool_redis::redis::aio;
 use rocket_db_pools::deadpool_redis::redis::{cmd, RedisError};
-use rocket_db_pools::Connection;
 use rocket_db_pools::{deadpool_redis, Database};
 use std::path::Path;
-use std::sync::LazyLock;
+use std::sync::{LazyLock, OnceLock};
+use std::time::UNIX_EPOCH;
 
 pub const TEN_MIB: usize = 10_485_760; // bytes; the per-item cache cap
 pub const SIXTY_FOUR_MIB: u64 = 67_108_864; // hard cap for buffering a single ZIP asset into RAM
 
 static ARXIV_ID_VERSION: LazyLock<Regex> = LazyLock::new(|| Regex::new("v\\d\\d?$").unwrap());
 
+/// Splits the trailing arXiv version off an id, e.g. "2105.04404v3" into
+/// ("2105.04404", Some("v3")). Unversioned ids come back whole, with `None`.
+pub fn split_arxiv_version(id: &str) -> (&str, Option<&str>) {
+  match ARXIV_ID_VERSION.find(id) {
+    Some(version) => (&id[..version.start()], Some(version.as_str())),
+    None => (id, None),
+  }
+}
+
+/// Splits the legacy field off an arXiv id, e.g. "math/0211159" into
+/// (Some("math"), "0211159") -- the inverse of `build_arxiv_id`.
+pub fn split_arxiv_field(id_arxiv: &str) -> (Option<&str>, &str) {
+  match id_arxiv.split_once('/') {
+    Some((field, id)) => (Some(field), id),
+    None => (None, id_arxiv),
+  }
+}
+
 /// Namespaced cache keys: papers, assets and conversion logs live in disjoint
 /// keyspaces, so that e.g. an asset literally named like the conversion log
 /// can never poison the log cache (or vice versa).
 pub fn paper_key(id_arxiv: &str) -> String {
   format!("p:{id_arxiv}")
 }
+pub fn page_key(id_arxiv: &str, page: &str) -> String {
+  format!("p:{id_arxiv}/{page}")
+}
 pub fn asset_key(id_arxiv: &str, filename: &str) -> String {
   format!("a:{id_arxiv}/{filename}")
 }
@@ -29,172 +74,782 @@ pub fn log_key(id_arxiv: &str) -> String {
   format!("l:{id_arxiv}")
 }
 
+/// The Redis pool: the `redis` cache backend, and the home of `paper_order`.
 #[derive(Database)]
 #[database("memdb")]
 pub struct Cache(deadpool_redis::Pool);
 
-pub async fn set_cached(
-  conn: &mut aio::MultiplexedConnection,
-  key: &str,
-  val: &str,
-) -> Result<(), ()> {
-  cmd("SET")
-    .arg(&[key, val])
+static CACHE_CONFIG: OnceLock<CacheConfig> = OnceLock::new();
+
+/// How long cached items live, from the `[cache]` table of Rocket.toml (in
+/// seconds; 0 for no expiry).
+///
+/// Every cache item expires, while the `paper_order` hash never does: under a
+/// `volatile-*` maxmemory policy, Redis then only ever evicts cache items, and
+/// the prev/next navigation survives memory pressure.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct CacheConfig {
+  /// Branded paper pages, `p:`.
+  pub paper_ttl: u64,
+  /// Conversion logs, `l:`.
+  pub log_ttl: u64,
+  /// Paper assets, `a:`.
+  pub asset_ttl: u64,
+  /// Assets larger than `large_asset_bytes` get the (shorter) `large_asset_ttl`:
+  /// they take the most memory for the fewest requests.
+  pub large_asset_ttl: u64,
+  pub large_asset_bytes: usize,
+  /// The maxmemory policy to set on Redis at launch; empty (the default)
+  /// leaves the server's own as is. Opt-in: it applies to the whole server,
+  /// and managed Redis instances refuse `CONFIG`.
+  pub maxmemory_policy: String,
+  /// Where items are cached: `redis`, `memory` (an LRU of `memory_bytes`, in
+  /// this process) or `disk` (under `disk_dir`).
+  pub backend: String,
+  pub memory_bytes: usize,
+  pub disk_dir: String,
+  /// The in-process tier in front of a `redis` or `disk` backend: its budget
+  /// (0: none), and how long it keeps values at most.
+  pub hot_bytes: usize,
+  pub hot_ttl: u64,
+  /// The coding cached pages and logs are stored in: `br`, `gzip`, or empty
+  /// to store them as is.
+  pub compression: String,
+  /// The circuit breaker of the backend: opened by `breaker_failures`
+  /// consecutive outages (or reads and writes taking over `timeout_ms`; 0 for
+  /// no limit), it skips the cache until a probe every `breaker_retry`
+  /// seconds finds the backend back.
+  pub breaker_failures: u32,
+  pub breaker_retry: u64,
+  pub timeout_ms: u64,
+}
+
+impl Default for CacheConfig {
+  fn default() -> Self {
+    CacheConfig {
+      paper_ttl: 604_800,
+      log_ttl: 259_200,
+      asset_ttl: 604_800,
+      large_asset_ttl: 86_400,
+      large_asset_bytes: 1_048_576,
+      maxmemory_policy: String::new(),
+      backend: String::from("redis"),
+      memory_bytes: 268_435_456,
+      disk_dir: String::from("cache"),
+      hot_bytes: 67_108_864,
+      hot_ttl: 60,
+      compression: String::from("br"),
+      breaker_failures: 5,
+      breaker_retry: 5,
+      timeout_ms: 1000,
+    }
+  }
+}
+
+impl CacheConfig {
+  /// The configuration in effect: Rocket.toml's, once the fairing has read it.
+  pub fn get() -> &'static CacheConfig {
+    CACHE_CONFIG.get_or_init(CacheConfig::default)
+  }
+
+  /// The lifetime of an item of `len` bytes, cached under `key`.
+  pub fn ttl(&self, key: &str, len: usize) -> u64 {
+    match key.split_once(':').map(|(keyspace, _)| keyspace) {
+      Some("a") if len > self.large_asset_bytes => self.large_asset_ttl,
+      Some("a") => self.asset_ttl,
+      Some("l") => self.log_ttl,
+      _ => self.paper_ttl,
+    }
+  }
+
+  /// Puts the `[cache]` table of a configuration in effect (unless one
+  /// already is).
+  pub fn load(figment: &Figment) -> &'static CacheConfig {
+    CACHE_CONFIG.get_or_init(|| match figment.extract_inner::<CacheConfig>("cache") {
+      Ok(config) => config,
+      Err(e) if e.missing() => CacheConfig::default(),
+      Err(e) => {
+        warn!("invalid [cache] configuration, using the defaults: {e}");
+        CacheConfig::default()
+      }
+    })
+  }
+
+  /// The coding to store pages and logs in, if any.
+  pub fn encoding(&self) -> Option<Encoding> {
+    Encoding::from_name(&self.compression)
+  }
+
+  /// The configured cache backend -- `None` for Redis without a pool.
+  pub fn backend(&self, redis: Option<deadpool_redis::Pool>) -> Option<PaperCache> {
+    let cache = match self.backend.as_str() {
+      "memory" => Some(PaperCache::new(MemoryCache::new(self.memory_bytes))),
+      "disk" => Some(
+        PaperCache::new(DiskCache::new(&self.disk_dir)).with_hot_tier(self.hot_bytes, self.hot_ttl),
+      ),
+      backend => {
+        if backend != "redis" {
+          warn!("unknown cache backend {backend:?}, using redis");
+        }
+        redis.map(|pool| {
+          PaperCache::new(RedisCache::new(pool)).with_hot_tier(self.hot_bytes, self.hot_ttl)
+        })
+      }
+    };
+    cache
+      .map(|cache| cache.with_breaker(self.breaker_failures, self.breaker_retry, self.timeout_ms))
+  }
+
+  /// Reads the `[cache]` table and sets up its backend at ignition; with
+  /// Redis, at liftoff also clears any expiry set on `paper_order`, sets the
+  /// maxmemory policy if so configured, and warns when the live one could
+  /// evict `paper_order`.
+  pub fn fairing() -> AdHoc {
+    AdHoc::on_ignite("Cache config", |rocket| async {
+      let config = CacheConfig::load(rocket.figment());
+      let pool = Cache::fetch(&rocket).map(|cache| cache.0.clone());
+      let rocket = match config.backend(pool) {
+        Some(cache) => rocket.manage(cache),
+        None => rocket,
+      };
+      rocket.attach(AdHoc::on_liftoff("Cache eviction policy", |rocket| {
+        Box::pin(async move {
+          let Some(pool) = Cache::fetch(rocket) else {
+            return;
+          };
+          let config = CacheConfig::get();
+          // (with another backend, Redis only holds `paper_order`)
+          let policy = match config.backend.as_str() {
+            "memory" | "disk" => "",
+            _ => config.maxmemory_policy.as_str(),
+          };
+          match pool.get().await {
+            Ok(mut conn) => protect_paper_order(&mut conn, policy).await,
+            Err(e) => warn!("cache unavailable, its eviction policy is left as is: {e}"),
+          }
+        })
+      }))
+    })
+  }
+}
+
+/// Makes sure `paper_order` has no expiry, sets the maxmemory policy (e.g.
+/// `volatile-lru`, which only evicts keys with an expiry) unless `policy` is
+/// empty, and warns when the live policy could evict `paper_order`.
+async fn protect_paper_order(conn: &mut aio::MultiplexedConnection, policy: &str) {
+  if !policy.is_empty() {
+    let set_policy = cmd("CONFIG")
+      .arg("SET")
+      .arg("maxmemory-policy")
+      .arg(policy)
+      .query_async::<_, ()>(conn)
+      .await;
+    if let Err(e) = set_policy {
+      warn!("could not set the Redis maxmemory policy to {policy}: {e}");
+    }
+  }
+  cmd("PERSIST")
+    .arg("paper_order")
     .query_async::<_, ()>(conn)
     .await
-    .map_err(|_| ())
+    .ok();
+  // (a server refusing `CONFIG` keeps its policy to itself)
+  let live = cmd("CONFIG")
+    .arg("GET")
+    .arg("maxmemory-policy")
+    .query_async::<_, Vec<String>>(conn)
+    .await;
+  if let Some(live) = live.ok().and_then(|mut reply| reply.pop()) {
+    if evicts_persistent_keys(&live) {
+      warn!(
+        "the Redis maxmemory policy {live} may evict paper_order (and with it the prev/next \
+         navigation): run Redis with a volatile-* policy, e.g. volatile-lru"
+      );
+    }
+  }
 }
 
-pub async fn get_cached(conn: &mut aio::MultiplexedConnection, key: &str) -> Result<String, ()> {
-  let value: Result<String, ()> = cmd("GET")
-    .arg(&[key])
-    .query_async::<_, String>(conn)
+/// Whether a maxmemory policy may evict keys without an expiry: the
+/// `allkeys-*` ones. (`volatile-*` only evict keys with one, `noeviction`
+/// none at all.)
+fn evicts_persistent_keys(policy: &str) -> bool {
+  policy.starts_with("allkeys-")
+}
+
+/// Identifies the state of a result bundle on disk. Cached items are stored
+/// under the fingerprint of the bundle they were made from, and are only
+/// served while it matches: when CorTeX drops a reprocessed bundle in place,
+/// everything cached from the old one turns into a miss (and gets replaced).
+pub fn bundle_fingerprint(path: &Path) -> Option<String> {
+  let metadata = std::fs::metadata(path).ok()?;
+  let mtime = metadata
+    .modified()
+    .ok()?
+    .duration_since(UNIX_EPOCH)
+    .ok()?
+    .as_nanos();
+  Some(format!("{mtime:x}-{:x}", metadata.len()))
+}
+
+/// The fingerprint of the bundle serving an id, if there is one.
+pub fn paper_fingerprint(field_opt: Option<&str>, id: &str) -> Option<String> {
+  bundle_fingerprint(&build_paper_path(field_opt, id).ok()?.path)
+}
+
+/// Records the paper requested in the access log, with the kind of the bundle
+/// found for it, if any -- and returns that bundle's fingerprint.
+fn log_paper(log: &RequestLog, field_opt: Option<&str>, id: &str) -> Option<String> {
+  let bundle = build_paper_path(field_opt, id).ok();
+  log.paper(
+    &build_arxiv_id(&field_opt, id),
+    bundle.as_ref().map(|bundle| bundle.kind),
+  );
+  bundle_fingerprint(&bundle?.path)
+}
+
+/// Caches a value under `key`, prefixed by the fingerprint of its bundle (and
+/// the coding of the value, if any), to expire after the lifetime of its
+/// keyspace.
+async fn set_expiring(
+  cache: &PaperCache,
+  key: &str,
+  fingerprint: &str,
+  encoding: Option<Encoding>,
+  val: &[u8],
+) -> Result<(), CacheError> {
+  let mut stored = Vec::with_capacity(fingerprint.len() + 6 + val.len());
+  stored.extend_from_slice(fingerprint.as_bytes());
+  if let Some(encoding) = encoding {
+    stored.push(b';');
+    stored.extend_from_slice(encoding.name().as_bytes());
+  }
+  stored.push(b'\n');
+  stored.extend_from_slice(val);
+  let ttl = CacheConfig::get().ttl(key, val.len());
+  cache.set(key, &stored, ttl).await
+}
+
+/// A cached value, if it was made from the bundle with this fingerprint --
+/// with the tier it was found in.
+async fn get_current(
+  cache: &PaperCache,
+  key: &str,
+  fingerprint: &str,
+) -> Result<(EncodedBody, CacheTier), CacheError> {
+  let current = cache
+    .get_from(key)
     .await
-    .map_err(|_| ());
-  value
+    .and_then(|(stored, tier)| Ok((strip_fingerprint(stored, fingerprint)?, tier)));
+  METRICS.cache_lookup(key, current.is_ok());
+  current
+}
+
+/// Splits a stored value into its `<fingerprint>[;<coding>]` header line and
+/// its body -- which is only current with a matching fingerprint.
+fn strip_fingerprint(stored: SharedBytes, fingerprint: &str) -> Result<EncodedBody, CacheError> {
+  // (the header is short: no need to look through a whole page for it)
+  let header_len = stored
+    .iter()
+    .take(fingerprint.len() + 6)
+    .position(|byte| *byte == b'\n')
+    .ok_or(CacheError::Miss)?;
+  let header = std::str::from_utf8(&stored[..header_len]).map_err(|_| CacheError::Miss)?;
+  let (stored_fingerprint, encoding) = match header.split_once(';') {
+    Some((stored_fingerprint, name)) => (
+      stored_fingerprint,
+      Some(Encoding::from_name(name).ok_or(CacheError::Miss)?),
+    ),
+    None => (header, None),
+  };
+  if stored_fingerprint == fingerprint {
+    Ok(EncodedBody {
+      body: stored.slice(header_len + 1..stored.len()),
+      encoding,
+    })
+  } else {
+    // made from an earlier bundle (or before fingerprints)
+    Err(CacheError::Miss)
+  }
+}
+
+/// Caches a page or log, compressed in the configured coding -- so that pages
+/// up to `TEN_MIB` compressed, several times that raw, fit in.
+pub async fn set_cached(
+  cache: &PaperCache,
+  key: &str,
+  fingerprint: &str,
+  val: &str,
+) -> Result<(), CacheError> {
+  match CacheConfig::get().encoding() {
+    Some(encoding) => {
+      let val = val.as_bytes().to_vec();
+      let compressed = spawn_blocking(move || encoding.compress(&val))
+        .await
+        .map_err(|e| CacheError::Failed(e.to_string()))??;
+      if compressed.len() > TEN_MIB {
+        return Err(CacheError::Rejected);
+      }
+      set_expiring(cache, key, fingerprint, Some(encoding), &compressed).await
+    }
+    None if val.len() <= TEN_MIB => {
+      set_expiring(cache, key, fingerprint, None, val.as_bytes()).await
+    }
+    None => Err(CacheError::Rejected),
+  }
+}
+
+/// A cached page or log, in the coding it was stored in.
+pub async fn get_cached(
+  cache: &PaperCache,
+  key: &str,
+  fingerprint: &str,
+) -> Result<(EncodedBody, CacheTier), CacheError> {
+  get_current(cache, key, fingerprint).await
 }
 
 pub async fn set_cached_asset(
-  conn: &mut aio::MultiplexedConnection,
+  cache: &PaperCache,
   key: &str,
+  fingerprint: &str,
   val: &[u8],
-) -> Result<(), ()> {
-  cmd("SET")
-    .arg(key)
-    .arg(val)
-    .query_async::<_, ()>(conn)
-    .await
-    .map_err(|_| ())
+) -> Result<(), CacheError> {
+  set_expiring(cache, key, fingerprint, None, val).await
 }
 pub async fn get_cached_asset(
-  conn: &mut aio::MultiplexedConnection,
+  cache: &PaperCache,
   key: &str,
-) -> Result<Vec<u8>, ()> {
-  let result: Result<Vec<u8>, ()> = cmd("GET")
-    .arg(&[key])
-    .query_async::<_, Vec<u8>>(conn)
-    .await
-    .map_err(|_| ());
-  match result {
-    Ok(value) => {
-      // guard: a successful asset get should not be empty
-      if value.is_empty() {
-        Err(())
-      } else {
-        Ok(value)
-      }
+  fingerprint: &str,
+) -> Result<(SharedBytes, CacheTier), CacheError> {
+  let (value, tier) = get_current(cache, key, fingerprint).await?;
+  // guard: a successful asset get should not be empty (nor compressed)
+  if value.body.is_empty() || value.encoding.is_some() {
+    Err(CacheError::Miss)
+  } else {
+    Ok((value.body, tier))
+  }
+}
+
+pub async fn hget_cached(cache: &PaperCache, hash: &str, key: &str) -> Result<String, CacheError> {
+  cache.hget(hash, key).await
+}
+
+/// The cached keys of an arXiv id, in all three keyspaces: its paper (and
+/// pages), log and assets -- for the id as well as any of its versions.
+pub async fn cached_keys(cache: &PaperCache, id_arxiv: &str) -> Result<Vec<String>, CacheError> {
+  let prefixes = ["p", "a", "l"].map(|keyspace| format!("{keyspace}:{id_arxiv}"));
+  let mut keys = cache.keys_starting_with(&prefixes).await?;
+  keys.retain(|key| is_key_of(key, id_arxiv));
+  // (a backend may return a key more than once)
+  keys.sort();
+  keys.dedup();
+  Ok(keys)
+}
+
+/// Whether a key starting with `[pal]:<id>` is really one of the id, and not
+/// of an id it merely prefixes.
+fn is_key_of(key: &str, id_arxiv: &str) -> bool {
+  match key.get(2..).and_then(|rest| rest.strip_prefix(id_arxiv)) {
+    Some(rest) => {
+      rest.is_empty()
+        || rest.starts_with('/')
+        || (rest.starts_with('v') && rest[1..].starts_with(|c: char| c.is_ascii_digit()))
     }
-    Err(e) => Err(e),
+    None => false,
   }
 }
 
-pub async fn hget_cached(
-  conn: &mut aio::MultiplexedConnection,
-  hash: &str,
+/// Describes every cached entry of an arXiv id.
+pub async fn inspect_cached(
+  cache: &PaperCache,
+  id_arxiv: &str,
+) -> Result<Vec<CachedEntry>, CacheError> {
+  let mut entries = Vec::new();
+  for key in cached_keys(cache, id_arxiv).await? {
+    // (an entry may expire between the two)
+    if let Ok(entry) = cache.describe(&key).await {
+      entries.push(entry);
+    }
+  }
+  Ok(entries)
+}
+
+/// Drops every cached entry of an arXiv id, returning how many there were.
+pub async fn purge_cached(cache: &PaperCache, id_arxiv: &str) -> Result<usize, CacheError> {
+  let keys = cached_keys(cache, id_arxiv).await?;
+  cache.remove(&keys).await
+}
+
+/// The assemblies in flight, by cache key: concurrent misses of a paper (or
+/// page), log or asset -- and concurrent reads of its metadata or parsed log
+/// -- wait for one assembly, rather than each decompressing the same bundle.
+type Assembled<T> = Result<T, AssembleError>;
+static PAPERS: LazyLock<SingleFlight<Assembled<SharedBytes>>> = LazyLock::new(SingleFlight::new);
+static LOGS: LazyLock<SingleFlight<Assembled<SharedBytes>>> = LazyLock::new(SingleFlight::new);
+static ASSETS: LazyLock<SingleFlight<Assembled<(PaperAsset, Validators)>>> =
+  LazyLock::new(SingleFlight::new);
+static METADATA: LazyLock<SingleFlight<Assembled<PaperMetadata>>> =
+  LazyLock::new(SingleFlight::new);
+static LOG_REPORTS: LazyLock<SingleFlight<Assembled<ConversionLog>>> =
+  LazyLock::new(SingleFlight::new);
+
+/// Reserves the memory assembling a cache miss from a paper's bundle is
+/// estimated to take: that of `entry`, or of the whole bundle. (Without a
+/// bundle, there is nothing to assemble.)
+async fn reserve_assembly(
+  field_opt: Option<&str>,
+  id: &str,
+  entry: Option<&str>,
+) -> Result<AssemblyPermit, AssembleError> {
+  let bundle = build_paper_path(field_opt, id)?;
+  Ok(
+    AssemblyBudget::get()
+      .reserve_for(bundle.path, entry.map(str::to_string))
+      .await?,
+  )
+}
+
+/// A cached page or log, as the client accepts it -- `None` on a miss.
+async fn cached_for_client(
+  cache: &PaperCache,
   key: &str,
-) -> Result<String, ()> {
-  let value: Result<String, ()> = cmd("HGET")
-    .arg(hash)
-    .arg(key)
-    .query_async::<_, String>(conn)
-    .await
-    .map_err(|_| ());
-  value
+  fingerprint: &str,
+  accept: &AcceptEncoding,
+) -> Option<(EncodedBody, CacheTier)> {
+  let (cached, tier) = get_cached(cache, key, fingerprint).await.ok()?;
+  if cached.body.is_empty() {
+    return None;
+  }
+  let cached = cached.for_client(accept).await.ok()?;
+  Some((cached, tier))
 }
 
 pub async fn assemble_paper_with_cache(
-  mut conn_opt: Option<Connection<Cache>>,
+  cache_opt: Option<PaperCache>,
   field_opt: Option<&str>,
-  id_raw: &str,
-) -> Option<String> {
-  let id = ARXIV_ID_VERSION.replace(id_raw, "");
-  let cached = match conn_opt {
-    Some(ref mut conn) => {
-      let key = paper_key(&build_arxiv_id(&field_opt, &id));
-      get_cached(&mut *conn, &key).await.unwrap_or_default()
-    }
-    None => String::default(),
+  id: &str,
+  accept: &AcceptEncoding,
+  log: &RequestLog,
+) -> Result<EncodedBody, AssembleError> {
+  let fingerprint = log_paper(log, field_opt, id);
+  // versioned requests (e.g. "2105.04404v3") are cached under their own keys,
+  // since they may be served from a version-specific bundle.
+  let cached = match (&cache_opt, fingerprint) {
+    (Some(cache), Some(fingerprint)) => {
+      let key = paper_key(&build_arxiv_id(&field_opt, id));
+      cached_for_client(cache, &key, &fingerprint, accept).await
+    }
+    _ => None,
   };
-  if !cached.is_empty() {
-    Some(cached)
+  if let Some((cached, tier)) = cached {
+    log.cache(Some(tier));
+    Ok(cached)
   } else {
-    assemble_paper(conn_opt, field_opt, &id).await
+    log.cache(None);
+    let key = paper_key(&build_arxiv_id(&field_opt, id));
+    PAPERS
+      .run(&key, || async {
+        let permit = reserve_assembly(field_opt, id, None).await?;
+        let (paper, stats, warm_up) = assemble_paper_warming(cache_opt, field_opt, id).await?;
+        log.assembly(&stats);
+        // (see `assemble_paper`; the assets stay in memory until warmed, and so
+        // does the reservation)
+        rocket::tokio::spawn(async move {
+          warm_up.await;
+          drop(permit);
+        });
+        Ok(SharedBytes::from(paper))
+      })
+      .await
+      .map(EncodedBody::identity)
+  }
+}
+
+pub async fn assemble_paper_page_with_cache(
+  cache_opt: Option<PaperCache>,
+  field_opt: Option<&str>,
+  id: &str,
+  page: &str,
+  accept: &AcceptEncoding,
+  log: &RequestLog,
+) -> Result<EncodedBody, AssembleError> {
+  let fingerprint = log_paper(log, field_opt, id);
+  let cached = match (&cache_opt, fingerprint) {
+    (Some(cache), Some(fingerprint)) => {
+      let key = page_key(&build_arxiv_id(&field_opt, id), page);
+      cached_for_client(cache, &key, &fingerprint, accept).await
+    }
+    _ => None,
+  };
+  if let Some((cached, tier)) = cached {
+    log.cache(Some(tier));
+    Ok(cached)
+  } else {
+    log.cache(None);
+    let key = page_key(&build_arxiv_id(&field_opt, id), page);
+    PAPERS
+      .run(&key, || async {
+        let _permit = reserve_assembly(field_opt, id, Some(page)).await?;
+        let html = assemble_paper_page(cache_opt, field_opt, id, page).await?;
+        Ok(SharedBytes::from(html))
+      })
+      .await
+      .map(EncodedBody::identity)
   }
 }
 
 pub async fn assemble_paper_asset_with_cache(
-  mut conn_opt: Option<Connection<Cache>>,
+  cache_opt: Option<PaperCache>,
   field_opt: Option<&str>,
-  id_raw: &str,
+  id: &str,
   filename: &str,
-) -> Result<(ContentType, Vec<u8>), Option<NamedFile>> {
-  let id = ARXIV_ID_VERSION.replace(id_raw, "");
-  let key = asset_key(&build_arxiv_id(&field_opt, &id), filename);
-  let cached = match conn_opt {
-    Some(ref mut conn) => get_cached_asset(&mut *conn, &key).await.unwrap_or_default(),
-    None => Vec::new(),
+  log: &RequestLog,
+) -> Result<Ranged, AssetFallback> {
+  let key = asset_key(&build_arxiv_id(&field_opt, id), filename);
+  // (taken before the bundle is read: should it get replaced in between, the
+  // new asset is cached under the old fingerprint, which only costs a miss)
+  let fingerprint = log_paper(log, field_opt, id).unwrap_or_default();
+  let (cached, tier) = match cache_opt {
+    Some(ref cache) => match get_cached_asset(cache, &key, &fingerprint).await {
+      Ok((cached, tier)) => (cached, Some(tier)),
+      Err(_) => (SharedBytes::default(), None),
+    },
+    None => (SharedBytes::default(), None),
   };
-  let asset_opt = if !cached.is_empty() {
-    Ok(cached)
-  } else if let Some(asset) = assemble_paper_asset(field_opt, &id, filename).await {
-    if asset.is_empty() {
-      Err(
-        NamedFile::open(Path::new("assets/missing_image.png"))
-          .await
-          .ok(),
-      )
-    } else {
-      if asset.len() <= TEN_MIB {
-        // cap cache items at 10 MiB
-        if let Some(ref mut conn) = conn_opt {
-          set_cached_asset(&mut *conn, &key, &asset).await.ok();
+  let cached_validators = if cached.is_empty() {
+    None
+  } else {
+    cached_asset_validators(field_opt, id, filename).await
+  };
+  log.cache(tier.filter(|_| cached_validators.is_some()));
+  let asset_opt = if let Some(validators) = cached_validators {
+    Ok((cached, validators))
+  } else {
+    let assembled = ASSETS
+      .run(&key, || async {
+        let _permit = reserve_assembly(field_opt, id, Some(filename)).await?;
+        assemble_paper_asset(field_opt, id, filename).await
+      })
+      .await;
+    let (asset, validators) = match assembled {
+      Ok(assembled) => assembled,
+      Err(AssembleError::NotFound) => {
+        return Err(AssetFallback::Missing(missing_asset(filename).await))
+      }
+      Err(e) => return Err(AssetFallback::Failed(e)),
+    };
+    match asset {
+      // too large to buffer (let alone cache): served from the bundle, as a
+      // range of its file when stored, else decompressed as it streams
+      PaperAsset::Oversized { bundle, len, head } => {
+        let content_type = asset_content_type(filename, &head);
+        let name = filename.to_string();
+        let stored = spawn_blocking({
+          let (bundle, name) = (bundle.clone(), name.clone());
+          move || ZIP_INDEXES.open_stored(&bundle, &name)
+        })
+        .await
+        .ok()
+        .flatten();
+        return Ok(match stored {
+          Some((file, start, len)) => Ranged::file_part(content_type, file, start, len, validators),
+          None => Ranged::zip_entry(content_type, bundle, name, len, validators),
+        });
+      }
+      PaperAsset::Buffered(asset) if asset.is_empty() => {
+        Err(AssetFallback::Missing(missing_asset(filename).await))
+      }
+      PaperAsset::Buffered(asset) => {
+        if asset.len() <= TEN_MIB {
+          // cap cache items at 10 MiB
+          if let Some(ref cache) = cache_opt {
+            set_cached_asset(cache, &key, &fingerprint, &asset)
+              .await
+              .ok();
+          }
+        } else {
+          METRICS.inc("ar5iv_skipped_assets_total", &[("cap", "ten_mib")]);
         }
+        Ok((asset, validators))
       }
-      Ok(asset)
     }
+  };
+
+  asset_opt.map(|(asset, validators)| {
+    Ranged::bytes(asset_content_type(filename, &asset), asset, validators)
+  })
+}
+
+/// Paper assets we cannot serve: missing ones, and those we failed to
+/// assemble (or turned away while the assembly budget is exhausted).
+#[derive(Responder)]
+pub enum AssetFallback {
+  Missing(Option<NamedFile>),
+  Failed(AssembleError),
+}
+
+/// A missing image is answered with a placeholder image, anything else (a
+/// stylesheet, a script, a page...) with a 404.
+async fn missing_asset(filename: &str) -> Option<NamedFile> {
+  if is_image_request(filename) {
+    NamedFile::open(Path::new("assets/missing_image.png"))
+      .await
+      .ok()
   } else {
-    Err(
-      NamedFile::open(Path::new("assets/missing_image.png"))
-        .await
-        .ok(),
-    )
+    None
+  }
+}
+
+/// A site-wide asset (e.g. `assets/ar5iv.png`, on every page), kept in the hot
+/// tier when there is one -- under `s:`, a keyspace of this process only,
+/// validated by the file's mtime and size.
+pub async fn site_asset_with_cache(cache_opt: Option<PaperCache>, path: &Path) -> Option<Ranged> {
+  let file = AsyncFile::open(path).await.ok()?.into_std().await;
+  let metadata = file.metadata().ok()?;
+  if !metadata.is_file() {
+    return None;
+  }
+  let name = path.to_string_lossy();
+  let validators = Validators::of_file(&metadata);
+  let cache = match cache_opt {
+    Some(cache) if cache.has_hot_tier() && metadata.len() <= TEN_MIB as u64 => cache,
+    _ => {
+      return Some(Ranged::file(
+        asset_content_type(&name, &[]),
+        file,
+        &metadata,
+      ))
+    }
+  };
+  let key = format!("s:{name}");
+  let cached = cache
+    .get_hot(&key)
+    .and_then(|stored| strip_fingerprint(stored, &validators.etag).ok())
+    .map(|cached| cached.body);
+  let bytes = match cached {
+    Some(bytes) => bytes,
+    None => {
+      let mut stored = validators.etag.clone().into_bytes();
+      stored.push(b'\n');
+      let mut file = AsyncFile::from_std(file);
+      file.read_to_end(&mut stored).await.ok()?;
+      let stored = SharedBytes::from(stored);
+      cache.set_hot(&key, stored.clone());
+      stored.slice(validators.etag.len() + 1..stored.len())
+    }
   };
+  Some(Ranged::bytes(
+    asset_content_type(&name, &bytes),
+    bytes,
+    validators,
+  ))
+}
 
-  asset_opt.map(|asset| {
-    (
-      ContentType::from_extension(filename.split('.').next_back().unwrap_or("png"))
-        .unwrap_or(ContentType::PNG),
-      asset,
-    )
+/// A cached asset carries the validators of its bundle entry, with the CRC-32
+/// the bundle's (indexed) central directory records for it -- rather than
+/// hashing the asset anew on every hit.
+async fn cached_asset_validators(
+  field_opt: Option<&str>,
+  id: &str,
+  name: &str,
+) -> Option<Validators> {
+  let bundle = build_paper_path(field_opt, id).ok()?.path;
+  let name = name.to_string();
+  spawn_blocking(move || {
+    let (zipf, index) = ZIP_INDEXES.open(&bundle).ok()?;
+    let crc32 = index.entry(&name)?.crc32;
+    Some(Validators::of_bundle_entry(&zipf.metadata().ok()?, crc32))
   })
+  .await
+  .ok()
+  .flatten()
 }
 
 pub async fn assemble_log_with_cache(
-  mut conn_opt: Option<Connection<Cache>>,
+  cache_opt: Option<PaperCache>,
   field_opt: Option<&str>,
-  id_raw: &str,
-) -> Option<String> {
-  let id = ARXIV_ID_VERSION.replace(id_raw, "");
-  let key = log_key(&build_arxiv_id(&field_opt, &id));
-  let cached = match conn_opt {
-    Some(ref mut conn) => get_cached(&mut *conn, &key).await.unwrap_or_default(),
-    None => String::new(),
+  id: &str,
+  accept: &AcceptEncoding,
+  log: &RequestLog,
+) -> Result<EncodedBody, AssembleError> {
+  let key = log_key(&build_arxiv_id(&field_opt, id));
+  let fingerprint = log_paper(log, field_opt, id).unwrap_or_default();
+  let cached = match cache_opt {
+    Some(ref cache) => cached_for_client(cache, &key, &fingerprint, accept).await,
+    None => None,
   };
-  if !cached.is_empty() {
-    Some(cached)
-  } else if let Some(paper) = assemble_log(field_opt, &id).await {
-    // cap cache items at 10 MiB
-    if !paper.is_empty() && paper.len() <= TEN_MIB {
-      if let Some(mut conn) = conn_opt {
-        set_cached(&mut conn, &key, paper.as_str()).await.ok();
-      }
-    }
-    Some(paper)
+  if let Some((cached, tier)) = cached {
+    log.cache(Some(tier));
+    Ok(cached)
   } else {
-    None
+    log.cache(None);
+    LOGS
+      .run(&key, || async {
+        let _permit = reserve_assembly(field_opt, id, Some(LOG_FILENAME)).await?;
+        let html_log = assemble_log(field_opt, id).await?;
+        if !html_log.is_empty() {
+          if let Some(cache) = cache_opt {
+            set_cached(&cache, &key, &fingerprint, html_log.as_str())
+              .await
+              .ok();
+          }
+        }
+        Ok(SharedBytes::from(html_log))
+      })
+      .await
+      .map(EncodedBody::identity)
   }
 }
 
+/// The parsed conversion log of a paper (for `/log/<id>.json`), within the
+/// assembly budget.
+pub async fn assemble_log_report_shared(
+  field_opt: Option<&str>,
+  id: &str,
+) -> Result<ConversionLog, AssembleError> {
+  LOG_REPORTS
+    .run(&build_arxiv_id(&field_opt, id), || async {
+      let _permit = reserve_assembly(field_opt, id, Some(LOG_FILENAME)).await?;
+      assemble_log_report(field_opt, id).await
+    })
+    .await
+}
+
+/// The metadata of a paper (for `/api/paper/<id>`), within the assembly
+/// budget -- reserved for the whole bundle, as its main page is only known
+/// once the bundle is open.
+pub async fn assemble_paper_metadata_shared(
+  cache_opt: Option<PaperCache>,
+  field_opt: Option<&str>,
+  id: &str,
+) -> Result<PaperMetadata, AssembleError> {
+  METADATA
+    .run(&build_arxiv_id(&field_opt, id), || async {
+      let _permit = reserve_assembly(field_opt, id, None).await?;
+      assemble_paper_metadata(cache_opt, field_opt, id).await
+    })
+    .await
+}
+
+/// Re-assembles (and so re-caches) a paper, within the assembly budget, and
+/// along with the requests missing it meanwhile.
+pub async fn prewarm_paper(
+  cache_opt: Option<PaperCache>,
+  field_opt: Option<&str>,
+  id: &str,
+) -> Result<(), AssembleError> {
+  let key = paper_key(&build_arxiv_id(&field_opt, id));
+  PAPERS
+    .run(&key, || async {
+      let _permit = reserve_assembly(field_opt, id, None).await?;
+      Ok(SharedBytes::from(
+        assemble_paper(cache_opt, field_opt, id).await?,
+      ))
+    })
+    .await
+    .map(|_| ())
+}
+
 /// We universally use the arxiv id scheme for both arxiv id refs and cache keys.
 pub fn build_arxiv_id(field_opt: &Option<&str>, id: &str) -> String {
   if let Some(ref field) = field_opt {
@@ -231,3 +886,116 @@ impl Default for LuckyStore {
     Self::new()
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn only_allkeys_policies_evict_paper_order() {
+    assert!(evicts_persistent_keys("allkeys-lru"));
+    assert!(evicts_persistent_keys("allkeys-random"));
+    assert!(!evicts_persistent_keys("volatile-lru"));
+    assert!(!evicts_persistent_keys("noeviction"));
+    assert_eq!(CacheConfig::default().maxmemory_policy, "");
+  }
+
+  #[test]
+  fn keyspaces_have_their_own_lifetimes() {
+    let config = CacheConfig::default();
+    assert_eq!(
+      config.ttl(&paper_key("2105.04404"), 100_000),
+      config.paper_ttl
+    );
+    assert_eq!(
+      config.ttl(&page_key("2105.04404", "Ch1.html"), 100_000),
+      config.paper_ttl
+    );
+    assert_eq!(
+      config.ttl(&log_key("math/0211159"), 100_000),
+      config.log_ttl
+    );
+    assert_eq!(
+      config.ttl(&asset_key("2105.04404", "x1.png"), 100_000),
+      config.asset_ttl
+    );
+    assert_eq!(
+      config.ttl(&asset_key("2105.04404", "x1.png"), 5_000_000),
+      config.large_asset_ttl
+    );
+    assert!(config.large_asset_ttl < config.paper_ttl);
+  }
+
+  #[test]
+  fn only_keys_of_the_id_and_its_versions_are_its_own() {
+    for key in [
+      "p:2105.04404",
+      "p:2105.04404/Ch1.html",
+      "p:2105.04404v3",
+      "l:2105.04404",
+      "a:2105.04404/x1.png",
+      "a:2105.04404v2/x1.png",
+    ] {
+      assert!(is_key_of(key, "2105.04404"), "{key}");
+    }
+    for key in ["p:2105.044041", "a:2105.04404value/x1.png", "p:2105.0440"] {
+      assert!(!is_key_of(key, "2105.04404"), "{key}");
+    }
+    assert!(is_key_of("a:math/0211159/x1.png", "math/0211159"));
+  }
+
+  #[test]
+  fn entries_of_an_earlier_bundle_are_misses() {
+    let stored = SharedBytes::from(b"18b2c-4f2\n<html></html>".to_vec());
+    assert_eq!(
+      strip_fingerprint(stored.clone(), "18b2c-4f2"),
+      Ok(EncodedBody::identity(SharedBytes::from(
+        b"<html></html>".to_vec()
+      )))
+    );
+    assert_eq!(
+      strip_fingerprint(stored.clone(), "18b2d-4f2"),
+      Err(CacheError::Miss)
+    );
+    assert_eq!(strip_fingerprint(stored, "18b2c-4f"), Err(CacheError::Miss));
+    assert_eq!(
+      strip_fingerprint(SharedBytes::from(b"<html></html>".to_vec()), "18b2c-4f2"),
+      Err(CacheError::Miss)
+    );
+    let compressed = SharedBytes::from(b"18b2c-4f2;br\n\x1b\x0c".to_vec());
+    assert_eq!(
+      strip_fingerprint(compressed.clone(), "18b2c-4f2"),
+      Ok(EncodedBody {
+        body: compressed.slice(13..15),
+        encoding: Some(Encoding::Brotli),
+      })
+    );
+  }
+
+  #[test]
+  fn reprocessed_bundles_get_a_new_fingerprint() {
+    let path =
+      std::env::temp_dir().join(format!("ar5iv_fingerprint_test_{}.zip", std::process::id()));
+    std::fs::write(&path, b"first conversion").unwrap();
+    let first = bundle_fingerprint(&path).unwrap();
+    // (longer, so the fingerprint changes even within one mtime tick)
+    std::fs::write(&path, b"second, longer conversion").unwrap();
+    assert_ne!(bundle_fingerprint(&path).unwrap(), first);
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(bundle_fingerprint(&path), None);
+  }
+
+  #[test]
+  fn arxiv_versions_split_off_ids() {
+    assert_eq!(
+      split_arxiv_version("2105.04404v3"),
+      ("2105.04404", Some("v3"))
+    );
+    assert_eq!(
+      split_arxiv_version("2105.04404v12"),
+      ("2105.04404", Some("v12"))
+    );
+    assert_eq!(split_arxiv_version("2105.04404"), ("2105.04404", None));
+    assert_eq!(split_arxiv_version("0211159v2"), ("0211159", Some("v2")));
+  }
+}
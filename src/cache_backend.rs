@@ -0,0 +1,1058 @@
+use rocket::http::Status;
+use rocket::request::{self, FromRequest, Request};
+use rocket::response::{self, Responder, Response};
+use rocket::serde::Serialize;
+use rocket::tokio::task::spawn_blocking;
+use rocket::tokio::time::{sleep, timeout};
+use rocket::warn;
+use rocket_db_pools::deadpool_redis::redis::{self, cmd};
+use rocket_db_pools::deadpool_redis::{self, Connection};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::future::Future;
+use std::io::{self, Cursor};
+use std::ops::{Deref, Range};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::circuit_breaker::{BreakerStats, CircuitBreaker};
+
+/// What is cached under a key, as reported to admins.
+#[derive(Debug, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct CachedEntry {
+  pub key: String,
+  /// stored size, fingerprint included
+  pub bytes: u64,
+  /// seconds left, or -1 when the key doesn't expire
+  pub ttl: i64,
+  /// of the bundle the entry was made from
+  pub fingerprint: String,
+}
+
+/// A cached value, shared rather than copied: a window into bytes that may
+/// also be held by the in-process tier. Cheap to clone and to slice.
+#[derive(Clone, Debug)]
+pub struct SharedBytes {
+  shared: Arc<[u8]>,
+  start: usize,
+  end: usize,
+}
+
+impl SharedBytes {
+  /// A part of these bytes, by a range relative to them.
+  pub fn slice(&self, range: Range<usize>) -> SharedBytes {
+    assert!(range.start <= range.end && range.end <= self.len());
+    SharedBytes {
+      shared: self.shared.clone(),
+      start: self.start + range.start,
+      end: self.start + range.end,
+    }
+  }
+}
+
+impl From<Vec<u8>> for SharedBytes {
+  fn from(bytes: Vec<u8>) -> Self {
+    let end = bytes.len();
+    SharedBytes {
+      shared: bytes.into(),
+      start: 0,
+      end,
+    }
+  }
+}
+
+impl Default for SharedBytes {
+  fn default() -> Self {
+    SharedBytes::from(Vec::new())
+  }
+}
+
+impl From<String> for SharedBytes {
+  fn from(text: String) -> Self {
+    SharedBytes::from(text.into_bytes())
+  }
+}
+
+impl Deref for SharedBytes {
+  type Target = [u8];
+  fn deref(&self) -> &[u8] {
+    &self.shared[self.start..self.end]
+  }
+}
+
+impl PartialEq for SharedBytes {
+  fn eq(&self, other: &SharedBytes) -> bool {
+    **self == **other
+  }
+}
+
+impl AsRef<[u8]> for SharedBytes {
+  fn as_ref(&self) -> &[u8] {
+    self
+  }
+}
+
+impl<'r> Responder<'r, 'static> for SharedBytes {
+  fn respond_to(self, _: &'r Request<'_>) -> response::Result<'static> {
+    Response::build()
+      .sized_body(self.len(), Cursor::new(self))
+      .ok()
+  }
+}
+
+/// Why the cache had no answer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CacheError {
+  /// Nothing current under the key: never cached, expired, evicted, or made
+  /// from an earlier bundle.
+  Miss,
+  /// A value the cache would not keep, e.g. one larger than its budget.
+  Rejected,
+  /// A backend without the operation, e.g. `hget` off Redis.
+  Unsupported,
+  /// The backend could not be reached, or did not answer in time: an outage,
+  /// counted by the circuit breaker.
+  Unavailable(String),
+  /// The backend answered with an error, for this operation only.
+  Failed(String),
+  /// The circuit breaker is open: the backend is skipped until it is back.
+  CircuitOpen,
+}
+
+impl fmt::Display for CacheError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      CacheError::Miss => write!(f, "cache miss"),
+      CacheError::Rejected => write!(f, "value rejected by the cache"),
+      CacheError::Unsupported => write!(f, "unsupported by this cache backend"),
+      CacheError::Unavailable(e) => write!(f, "cache unavailable: {e}"),
+      CacheError::Failed(e) => write!(f, "cache operation failed: {e}"),
+      CacheError::CircuitOpen => write!(f, "cache skipped, its circuit breaker is open"),
+    }
+  }
+}
+
+impl std::error::Error for CacheError {}
+
+impl From<redis::RedisError> for CacheError {
+  fn from(e: redis::RedisError) -> Self {
+    if e.is_io_error() || e.is_connection_dropped() || e.is_connection_refusal() || e.is_timeout() {
+      CacheError::Unavailable(e.to_string())
+    } else {
+      CacheError::Failed(e.to_string())
+    }
+  }
+}
+
+impl From<io::Error> for CacheError {
+  fn from(e: io::Error) -> Self {
+    match e.kind() {
+      io::ErrorKind::NotFound => CacheError::Miss,
+      _ => CacheError::Failed(e.to_string()),
+    }
+  }
+}
+
+/// Where cached papers, pages, logs and assets are kept. The keyspaces,
+/// fingerprints and lifetimes are up to `cache.rs`; a backend only stores
+/// bytes under keys.
+#[rocket::async_trait]
+pub trait CacheBackend: Send + Sync {
+  async fn get(&self, key: &str) -> Result<SharedBytes, CacheError>;
+  /// Stores a value for `ttl` seconds (0: without expiry).
+  async fn set(&self, key: &str, val: &[u8], ttl: u64) -> Result<(), CacheError>;
+  /// A field of a hash, such as `paper_order` -- which only Redis holds.
+  async fn hget(&self, _hash: &str, _field: &str) -> Result<String, CacheError> {
+    Err(CacheError::Unsupported)
+  }
+  /// The keys starting with any of `prefixes`.
+  async fn keys_starting_with(&self, prefixes: &[String]) -> Result<Vec<String>, CacheError>;
+  /// Removes keys, returning how many there were.
+  async fn remove(&self, keys: &[String]) -> Result<usize, CacheError>;
+  async fn describe(&self, key: &str) -> Result<CachedEntry, CacheError>;
+  /// Whether the backend is reachable, probed while its circuit is open.
+  async fn ping(&self) -> Result<(), CacheError> {
+    Ok(())
+  }
+}
+
+/// The cache in use, shared by all requests (and a request guard): a backend,
+/// optionally behind an in-process hot tier, and behind a circuit breaker --
+/// while the backend is out, requests skip it (the hot tier aside) rather than
+/// each waiting on it.
+#[derive(Clone)]
+pub struct PaperCache {
+  backend: Arc<dyn CacheBackend>,
+  hot: Option<Arc<HotTier>>,
+  breaker: Arc<CircuitBreaker>,
+  /// How long a request waits on the backend for a value at most.
+  timeout: Option<Duration>,
+}
+
+/// The most recently read values of a shared backend, kept in this process:
+/// a hit hands out the value itself, rather than a copy over the socket.
+///
+/// Values are dropped from the tier whenever they are written or purged
+/// through this process, and otherwise live for at most `ttl` seconds -- the
+/// delay before purges made elsewhere (another process sharing the Redis, a
+/// `redis-cli DEL`) show.
+struct HotTier {
+  lru: MemoryCache,
+  ttl: u64,
+  hits: AtomicU64,
+  misses: AtomicU64,
+}
+
+/// Where a cached value was found: in the hot tier, or the backend behind it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(crate = "rocket::serde", rename_all = "lowercase")]
+pub enum CacheTier {
+  Hot,
+  Backend,
+}
+
+/// How the hot tier is doing, as reported to admins.
+#[derive(Debug, Default, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct HotTierStats {
+  pub hits: u64,
+  pub misses: u64,
+  pub entries: usize,
+  pub bytes: usize,
+  pub budget: usize,
+}
+
+impl PaperCache {
+  pub fn new(backend: impl CacheBackend + 'static) -> Self {
+    PaperCache {
+      backend: Arc::new(backend),
+      hot: None,
+      breaker: Arc::new(CircuitBreaker::default()),
+      timeout: None,
+    }
+  }
+
+  /// Opens the circuit after `failures` consecutive outages, probing the
+  /// backend every `retry` seconds until it answers; the reads and writes of
+  /// requests give up after `timeout_ms` (0: wait for the backend).
+  pub fn with_breaker(mut self, failures: u32, retry: u64, timeout_ms: u64) -> Self {
+    self.breaker = Arc::new(CircuitBreaker::new(failures, Duration::from_secs(retry)));
+    self.timeout = (timeout_ms > 0).then(|| Duration::from_millis(timeout_ms));
+    self
+  }
+
+  /// Runs an operation on the backend -- unless its circuit is open --
+  /// counting its outcome.
+  async fn guarded<T>(
+    &self,
+    operation: impl Future<Output = Result<T, CacheError>>,
+    bounded: bool,
+  ) -> Result<T, CacheError> {
+    if self.breaker.is_open() {
+      return Err(CacheError::CircuitOpen);
+    }
+    let result = match self.timeout.filter(|_| bounded) {
+      Some(limit) => timeout(limit, operation)
+        .await
+        .unwrap_or_else(|_| Err(CacheError::Unavailable(format!("no answer in {limit:?}")))),
+      None => operation.await,
+    };
+    match result {
+      Err(CacheError::Unavailable(ref e)) => {
+        if self.breaker.record_failure(e) {
+          warn!("cache circuit opened, skipping the cache: {e}");
+          self.probe();
+        }
+      }
+      _ => self.breaker.record_success(),
+    }
+    result
+  }
+
+  /// Probes the backend in the background until it answers, then closes the
+  /// circuit.
+  fn probe(&self) {
+    let backend = self.backend.clone();
+    let breaker = self.breaker.clone();
+    rocket::tokio::spawn(async move {
+      loop {
+        sleep(breaker.retry()).await;
+        if let Ok(Ok(())) = timeout(breaker.retry(), backend.ping()).await {
+          breaker.close();
+          warn!("cache back, circuit closed");
+          break;
+        }
+      }
+    });
+  }
+
+  /// The state of the circuit breaker.
+  pub fn breaker_stats(&self) -> BreakerStats {
+    self.breaker.stats()
+  }
+
+  /// Puts a hot tier of `budget` bytes in front of the backend, keeping
+  /// values for up to `ttl` seconds (0: no tier).
+  pub fn with_hot_tier(mut self, budget: usize, ttl: u64) -> Self {
+    self.hot = (budget > 0 && ttl > 0).then(|| {
+      Arc::new(HotTier {
+        lru: MemoryCache::new(budget),
+        ttl,
+        hits: AtomicU64::new(0),
+        misses: AtomicU64::new(0),
+      })
+    });
+    self
+  }
+
+  pub async fn get(&self, key: &str) -> Result<SharedBytes, CacheError> {
+    self.get_from(key).await.map(|(val, _)| val)
+  }
+
+  /// A value, with the tier it was found in.
+  pub async fn get_from(&self, key: &str) -> Result<(SharedBytes, CacheTier), CacheError> {
+    let Some(hot) = &self.hot else {
+      let val = self.guarded(self.backend.get(key), true).await?;
+      return Ok((val, CacheTier::Backend));
+    };
+    if let Some(val) = hot.lru.lookup(key) {
+      hot.hits.fetch_add(1, Ordering::Relaxed);
+      return Ok((val, CacheTier::Hot));
+    }
+    hot.misses.fetch_add(1, Ordering::Relaxed);
+    let val = self.guarded(self.backend.get(key), true).await?;
+    hot.lru.insert(key, val.clone(), hot.ttl).ok();
+    Ok((val, CacheTier::Backend))
+  }
+
+  pub async fn set(&self, key: &str, val: &[u8], ttl: u64) -> Result<(), CacheError> {
+    if let Some(hot) = &self.hot {
+      // (filled again by the next read)
+      hot.lru.evict(&[key]);
+    }
+    self.guarded(self.backend.set(key, val, ttl), true).await
+  }
+
+  pub async fn hget(&self, hash: &str, field: &str) -> Result<String, CacheError> {
+    self.guarded(self.backend.hget(hash, field), true).await
+  }
+
+  /// The keys starting with any of `prefixes`, in either tier.
+  pub async fn keys_starting_with(&self, prefixes: &[String]) -> Result<Vec<String>, CacheError> {
+    let mut keys = self
+      .guarded(self.backend.keys_starting_with(prefixes), false)
+      .await?;
+    if let Some(hot) = &self.hot {
+      keys.extend(hot.lru.keys_with(prefixes));
+    }
+    Ok(keys)
+  }
+
+  /// Removes keys from both tiers, returning how many the backend had.
+  pub async fn remove(&self, keys: &[String]) -> Result<usize, CacheError> {
+    if let Some(hot) = &self.hot {
+      hot.lru.evict(keys);
+    }
+    self.guarded(self.backend.remove(keys), false).await
+  }
+
+  pub async fn describe(&self, key: &str) -> Result<CachedEntry, CacheError> {
+    self.guarded(self.backend.describe(key), false).await
+  }
+
+  pub fn has_hot_tier(&self) -> bool {
+    self.hot.is_some()
+  }
+
+  /// A value of the hot tier only, for files this process serves itself.
+  pub fn get_hot(&self, key: &str) -> Option<SharedBytes> {
+    let hot = self.hot.as_ref()?;
+    let val = hot.lru.lookup(key);
+    let counter = if val.is_some() {
+      &hot.hits
+    } else {
+      &hot.misses
+    };
+    counter.fetch_add(1, Ordering::Relaxed);
+    val
+  }
+
+  /// Keeps a value in the hot tier only (if there is one).
+  pub fn set_hot(&self, key: &str, val: SharedBytes) {
+    if let Some(hot) = &self.hot {
+      hot.lru.insert(key, val, hot.ttl).ok();
+    }
+  }
+
+  /// The hot tier's counters and usage, if there is one.
+  pub fn hot_tier_stats(&self) -> Option<HotTierStats> {
+    let hot = self.hot.as_ref()?;
+    let (entries, bytes) = hot.lru.usage();
+    Some(HotTierStats {
+      hits: hot.hits.load(Ordering::Relaxed),
+      misses: hot.misses.load(Ordering::Relaxed),
+      entries,
+      bytes,
+      budget: hot.lru.budget,
+    })
+  }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for PaperCache {
+  type Error = ();
+  async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, ()> {
+    match req.rocket().state::<PaperCache>() {
+      Some(cache) => request::Outcome::Success(cache.clone()),
+      None => request::Outcome::Forward(Status::ServiceUnavailable),
+    }
+  }
+}
+
+/// The fingerprint a stored value starts with, up to its newline.
+fn stored_fingerprint(head: &[u8]) -> String {
+  let fingerprint = head.split(|byte| *byte == b'\n').next().unwrap_or_default();
+  String::from_utf8_lossy(fingerprint).into_owned()
+}
+
+/// Today's cache: a Redis instance, shared by all ar5iv processes.
+pub struct RedisCache(deadpool_redis::Pool);
+
+impl RedisCache {
+  pub fn new(pool: deadpool_redis::Pool) -> Self {
+    RedisCache(pool)
+  }
+
+  async fn conn(&self) -> Result<Connection, CacheError> {
+    self
+      .0
+      .get()
+      .await
+      .map_err(|e| CacheError::Unavailable(e.to_string()))
+  }
+}
+
+#[rocket::async_trait]
+impl CacheBackend for RedisCache {
+  async fn get(&self, key: &str) -> Result<SharedBytes, CacheError> {
+    cmd("GET")
+      .arg(key)
+      .query_async::<_, Option<Vec<u8>>>(&mut *self.conn().await?)
+      .await?
+      .map(SharedBytes::from)
+      .ok_or(CacheError::Miss)
+  }
+
+  async fn set(&self, key: &str, val: &[u8], ttl: u64) -> Result<(), CacheError> {
+    let mut set = cmd("SET");
+    set.arg(key).arg(val);
+    if ttl > 0 {
+      set.arg("EX").arg(ttl);
+    }
+    set.query_async::<_, ()>(&mut *self.conn().await?).await?;
+    Ok(())
+  }
+
+  async fn hget(&self, hash: &str, field: &str) -> Result<String, CacheError> {
+    cmd("HGET")
+      .arg(hash)
+      .arg(field)
+      .query_async::<_, Option<String>>(&mut *self.conn().await?)
+      .await?
+      .ok_or(CacheError::Miss)
+  }
+
+  async fn keys_starting_with(&self, prefixes: &[String]) -> Result<Vec<String>, CacheError> {
+    let mut conn = self.conn().await?;
+    let mut keys = Vec::new();
+    for prefix in prefixes {
+      let pattern = escape_glob(prefix) + "*";
+      let mut cursor = 0;
+      loop {
+        let (next, batch) = cmd("SCAN")
+          .arg(cursor)
+          .arg("MATCH")
+          .arg(&pattern)
+          .arg("COUNT")
+          .arg(1000)
+          .query_async::<_, (u64, Vec<String>)>(&mut *conn)
+          .await?;
+        keys.extend(batch);
+        if next == 0 {
+          break;
+        }
+        cursor = next;
+      }
+    }
+    Ok(keys)
+  }
+
+  async fn remove(&self, keys: &[String]) -> Result<usize, CacheError> {
+    let mut conn = self.conn().await?;
+    let mut removed = 0;
+    for batch in keys.chunks(512) {
+      removed += cmd("UNLINK")
+        .arg(batch)
+        .query_async::<_, usize>(&mut *conn)
+        .await?;
+    }
+    Ok(removed)
+  }
+
+  async fn describe(&self, key: &str) -> Result<CachedEntry, CacheError> {
+    let (bytes, ttl, head) = redis::pipe()
+      .cmd("STRLEN")
+      .arg(key)
+      .cmd("TTL")
+      .arg(key)
+      .cmd("GETRANGE")
+      .arg(key)
+      .arg(0)
+      .arg(63)
+      .query_async::<_, (u64, i64, Vec<u8>)>(&mut *self.conn().await?)
+      .await?;
+    if ttl == -2 {
+      // (no such key)
+      return Err(CacheError::Miss);
+    }
+    Ok(CachedEntry {
+      key: key.to_string(),
+      bytes,
+      ttl,
+      fingerprint: stored_fingerprint(&head),
+    })
+  }
+
+  async fn ping(&self) -> Result<(), CacheError> {
+    cmd("PING")
+      .query_async::<_, String>(&mut *self.conn().await?)
+      .await?;
+    Ok(())
+  }
+}
+
+fn escape_glob(text: &str) -> String {
+  let mut escaped = String::with_capacity(text.len());
+  for c in text.chars() {
+    if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+      escaped.push('\\');
+    }
+    escaped.push(c);
+  }
+  escaped
+}
+
+/// A bounded in-process cache, evicting the least recently used entries past
+/// its byte budget: for single-process deployments and tests, without Redis.
+pub struct MemoryCache {
+  budget: usize,
+  state: Mutex<MemoryState>,
+}
+
+#[derive(Default)]
+struct MemoryState {
+  entries: HashMap<String, MemoryEntry>,
+  /// keys by their last use, least recent first
+  recency: BTreeMap<u64, String>,
+  clock: u64,
+  bytes: usize,
+}
+
+struct MemoryEntry {
+  val: SharedBytes,
+  expires: Option<Instant>,
+  used: u64,
+}
+
+impl MemoryEntry {
+  fn is_expired(&self) -> bool {
+    self
+      .expires
+      .is_some_and(|expires| expires <= Instant::now())
+  }
+}
+
+impl MemoryState {
+  fn remove(&mut self, key: &str) -> bool {
+    match self.entries.remove(key) {
+      Some(entry) => {
+        self.recency.remove(&entry.used);
+        self.bytes -= key.len() + entry.val.len();
+        true
+      }
+      None => false,
+    }
+  }
+}
+
+impl MemoryCache {
+  pub fn new(budget: usize) -> Self {
+    MemoryCache {
+      budget,
+      state: Mutex::new(MemoryState::default()),
+    }
+  }
+
+  /// A value, marked as the most recently used.
+  fn lookup(&self, key: &str) -> Option<SharedBytes> {
+    let mut state = self.state.lock().ok()?;
+    let state = &mut *state;
+    let entry = state.entries.get_mut(key)?;
+    if entry.is_expired() {
+      state.remove(key);
+      return None;
+    }
+    state.clock += 1;
+    state.recency.remove(&entry.used);
+    entry.used = state.clock;
+    state.recency.insert(entry.used, key.to_string());
+    Some(entry.val.clone())
+  }
+
+  /// Keeps a value, evicting the least recently used ones to make room -- unless
+  /// it is larger than the whole budget.
+  fn insert(&self, key: &str, val: SharedBytes, ttl: u64) -> Result<(), CacheError> {
+    let mut state = self.state.lock().map_err(|_| CacheError::Rejected)?;
+    state.remove(key);
+    let size = key.len() + val.len();
+    if size > self.budget {
+      return Err(CacheError::Rejected);
+    }
+    while state.bytes + size > self.budget {
+      let Some((_, least_recent)) = state.recency.pop_first() else {
+        break;
+      };
+      state.remove(&least_recent);
+    }
+    state.clock += 1;
+    let used = state.clock;
+    state.recency.insert(used, key.to_string());
+    state.bytes += size;
+    state.entries.insert(
+      key.to_string(),
+      MemoryEntry {
+        val,
+        expires: (ttl > 0).then(|| Instant::now() + Duration::from_secs(ttl)),
+        used,
+      },
+    );
+    Ok(())
+  }
+
+  fn evict(&self, keys: &[impl AsRef<str>]) -> usize {
+    match self.state.lock() {
+      Ok(mut state) => keys.iter().filter(|key| state.remove(key.as_ref())).count(),
+      Err(_) => 0,
+    }
+  }
+
+  fn keys_with(&self, prefixes: &[String]) -> Vec<String> {
+    let Ok(state) = self.state.lock() else {
+      return Vec::new();
+    };
+    state
+      .entries
+      .iter()
+      .filter(|(key, entry)| {
+        !entry.is_expired()
+          && prefixes
+            .iter()
+            .any(|prefix| key.starts_with(prefix.as_str()))
+      })
+      .map(|(key, _)| key.clone())
+      .collect()
+  }
+
+  /// How many entries there are, and how many bytes they take.
+  fn usage(&self) -> (usize, usize) {
+    self
+      .state
+      .lock()
+      .map_or((0, 0), |state| (state.entries.len(), state.bytes))
+  }
+}
+
+#[rocket::async_trait]
+impl CacheBackend for MemoryCache {
+  async fn get(&self, key: &str) -> Result<SharedBytes, CacheError> {
+    self.lookup(key).ok_or(CacheError::Miss)
+  }
+
+  async fn set(&self, key: &str, val: &[u8], ttl: u64) -> Result<(), CacheError> {
+    self.insert(key, SharedBytes::from(val.to_vec()), ttl)
+  }
+
+  async fn keys_starting_with(&self, prefixes: &[String]) -> Result<Vec<String>, CacheError> {
+    Ok(self.keys_with(prefixes))
+  }
+
+  async fn remove(&self, keys: &[String]) -> Result<usize, CacheError> {
+    Ok(self.evict(keys))
+  }
+
+  async fn describe(&self, key: &str) -> Result<CachedEntry, CacheError> {
+    let state = self.state.lock().map_err(|_| CacheError::Miss)?;
+    let entry = state
+      .entries
+      .get(key)
+      .filter(|entry| !entry.is_expired())
+      .ok_or(CacheError::Miss)?;
+    Ok(CachedEntry {
+      key: key.to_string(),
+      bytes: entry.val.len() as u64,
+      ttl: entry.expires.map_or(-1, |expires| {
+        expires.saturating_duration_since(Instant::now()).as_secs() as i64
+      }),
+      fingerprint: stored_fingerprint(&entry.val),
+    })
+  }
+}
+
+/// A content-addressed cache on disk, which survives restarts without a Redis
+/// to keep it:
+///
+/// - `objects/ab/cdef...` hold the values, named by their BLAKE3 hash -- so
+///   that the assets shared by many papers (stylesheets, logos) are stored once;
+/// - `keys/<hash of the key>` point at them, as `<expiry>\n<object>\n<key>`
+///   (the expiry in seconds since the epoch, 0 for none).
+///
+/// Objects no key points at any more are left behind; emptying the directory
+/// is always safe.
+pub struct DiskCache {
+  dir: PathBuf,
+}
+
+/// Distinguishes the temporary files of concurrent writes.
+static TEMPORARY_FILES: AtomicU64 = AtomicU64::new(0);
+
+/// A key's pointer at its object.
+struct DiskKey {
+  expires: u64,
+  object: String,
+  key: String,
+}
+
+impl DiskKey {
+  fn read(path: &Path) -> io::Result<DiskKey> {
+    let contents = fs::read_to_string(path)?;
+    let mut lines = contents.splitn(3, '\n');
+    let (Some(expires), Some(object), Some(key)) = (lines.next(), lines.next(), lines.next())
+    else {
+      return Err(io::Error::from(io::ErrorKind::InvalidData));
+    };
+    Ok(DiskKey {
+      expires: expires.parse().map_err(io::Error::other)?,
+      object: object.to_string(),
+      key: key.to_string(),
+    })
+  }
+
+  fn is_expired(&self) -> bool {
+    self.expires > 0 && self.expires <= unix_time()
+  }
+}
+
+fn unix_time() -> u64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map_or(0, |since| since.as_secs())
+}
+
+/// Writes a file in one piece: to a temporary file, then renamed over it.
+fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+  let dir = path.parent().ok_or(io::ErrorKind::InvalidInput)?;
+  fs::create_dir_all(dir)?;
+  let temporary = dir.join(format!(
+    ".{}.{}.tmp",
+    std::process::id(),
+    TEMPORARY_FILES.fetch_add(1, Ordering::Relaxed)
+  ));
+  fs::write(&temporary, contents)?;
+  fs::rename(&temporary, path).inspect_err(|_| {
+    fs::remove_file(&temporary).ok();
+  })
+}
+
+impl DiskCache {
+  pub fn new(dir: impl Into<PathBuf>) -> Self {
+    DiskCache { dir: dir.into() }
+  }
+
+  fn key_path(dir: &Path, key: &str) -> PathBuf {
+    dir
+      .join("keys")
+      .join(blake3::hash(key.as_bytes()).to_hex().as_str())
+  }
+
+  fn object_path(dir: &Path, object: &str) -> PathBuf {
+    let (fan_out, rest) = object.split_at(2.min(object.len()));
+    dir.join("objects").join(fan_out).join(rest)
+  }
+
+  /// Runs a blocking file-system operation on the cache directory.
+  async fn with_dir<T: Send + 'static>(
+    &self,
+    operation: impl FnOnce(&Path) -> io::Result<T> + Send + 'static,
+  ) -> Result<T, CacheError> {
+    let dir = self.dir.clone();
+    Ok(
+      spawn_blocking(move || operation(&dir))
+        .await
+        .map_err(|e| CacheError::Failed(e.to_string()))??,
+    )
+  }
+}
+
+#[rocket::async_trait]
+impl CacheBackend for DiskCache {
+  async fn get(&self, key: &str) -> Result<SharedBytes, CacheError> {
+    let key = key.to_string();
+    self
+      .with_dir(move |dir| {
+        let key_path = DiskCache::key_path(dir, &key);
+        let pointer = DiskKey::read(&key_path)?;
+        if pointer.is_expired() || pointer.key != key {
+          fs::remove_file(&key_path).ok();
+          return Err(io::Error::from(io::ErrorKind::NotFound));
+        }
+        fs::read(DiskCache::object_path(dir, &pointer.object)).map(SharedBytes::from)
+      })
+      .await
+  }
+
+  async fn set(&self, key: &str, val: &[u8], ttl: u64) -> Result<(), CacheError> {
+    let key = key.to_string();
+    let val = val.to_vec();
+    self
+      .with_dir(move |dir| {
+        let object = blake3::hash(&val).to_hex().to_string();
+        let object_path = DiskCache::object_path(dir, &object);
+        if !object_path.exists() {
+          write_atomically(&object_path, &val)?;
+        }
+        let expires = if ttl > 0 { unix_time() + ttl } else { 0 };
+        write_atomically(
+          &DiskCache::key_path(dir, &key),
+          format!("{expires}\n{object}\n{key}").as_bytes(),
+        )
+      })
+      .await
+  }
+
+  async fn keys_starting_with(&self, prefixes: &[String]) -> Result<Vec<String>, CacheError> {
+    let prefixes = prefixes.to_vec();
+    self
+      .with_dir(move |dir| {
+        let entries = match fs::read_dir(dir.join("keys")) {
+          Ok(entries) => entries,
+          Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+          Err(e) => return Err(e),
+        };
+        Ok(
+          entries
+            .flatten()
+            .filter_map(|entry| DiskKey::read(&entry.path()).ok())
+            .filter(|pointer| {
+              !pointer.is_expired()
+                && prefixes
+                  .iter()
+                  .any(|prefix| pointer.key.starts_with(prefix.as_str()))
+            })
+            .map(|pointer| pointer.key)
+            .collect(),
+        )
+      })
+      .await
+  }
+
+  async fn remove(&self, keys: &[String]) -> Result<usize, CacheError> {
+    let keys = keys.to_vec();
+    self
+      .with_dir(move |dir| {
+        Ok(
+          keys
+            .iter()
+            .filter(|key| fs::remove_file(DiskCache::key_path(dir, key)).is_ok())
+            .count(),
+        )
+      })
+      .await
+  }
+
+  async fn describe(&self, key: &str) -> Result<CachedEntry, CacheError> {
+    let key = key.to_string();
+    self
+      .with_dir(move |dir| {
+        let pointer = DiskKey::read(&DiskCache::key_path(dir, &key))?;
+        if pointer.is_expired() {
+          return Err(io::Error::from(io::ErrorKind::NotFound));
+        }
+        let object_path = DiskCache::object_path(dir, &pointer.object);
+        let mut head = Vec::with_capacity(64);
+        io::Read::read_to_end(
+          &mut io::Read::take(fs::File::open(&object_path)?, 64),
+          &mut head,
+        )?;
+        Ok(CachedEntry {
+          bytes: fs::metadata(&object_path)?.len(),
+          ttl: if pointer.expires > 0 {
+            pointer.expires.saturating_sub(unix_time()) as i64
+          } else {
+            -1
+          },
+          fingerprint: stored_fingerprint(&head),
+          key,
+        })
+      })
+      .await
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[rocket::async_test]
+  async fn memory_cache_evicts_the_least_recently_used() {
+    let cache = MemoryCache::new(30);
+    cache.set("a:1", b"0123456789", 0).await.unwrap();
+    cache.set("a:2", b"0123456789", 0).await.unwrap();
+    // a:1 is now the more recently used of the two
+    assert_eq!(&*cache.get("a:1").await.unwrap(), b"0123456789");
+    cache.set("a:3", b"0123456789", 0).await.unwrap();
+    assert_eq!(cache.get("a:2").await, Err(CacheError::Miss));
+    assert!(cache.get("a:1").await.is_ok());
+    assert!(cache.get("a:3").await.is_ok());
+    // too large to hold at all
+    assert_eq!(
+      cache.set("a:4", &[0; 64], 0).await,
+      Err(CacheError::Rejected)
+    );
+    assert_eq!(cache.remove(&[String::from("a:1")]).await, Ok(1));
+    assert_eq!(
+      cache.keys_starting_with(&[String::from("a:")]).await,
+      Ok(vec![String::from("a:3")])
+    );
+  }
+
+  #[rocket::async_test]
+  async fn hot_tier_shares_values_until_they_change() {
+    let cache = PaperCache::new(MemoryCache::new(1024)).with_hot_tier(1024, 60);
+    cache.set("p:1", b"fp\n<html>", 0).await.unwrap();
+    let first = cache.get("p:1").await.unwrap();
+    let second = cache.get("p:1").await.unwrap();
+    assert!(Arc::ptr_eq(&first.shared, &second.shared));
+    assert_eq!(&*second.slice(3..9), b"<html>");
+    let stats = cache.hot_tier_stats().unwrap();
+    assert_eq!((stats.hits, stats.misses, stats.entries), (1, 1, 1));
+
+    // writes and purges drop the hot copy, too
+    cache.set("p:1", b"fp2\n<html>", 0).await.unwrap();
+    assert_eq!(&*cache.get("p:1").await.unwrap(), b"fp2\n<html>");
+    cache.backend.remove(&[String::from("p:1")]).await.unwrap();
+    assert!(cache.get("p:1").await.is_ok());
+    let keys = cache.keys_starting_with(&[String::from("p:")]).await;
+    assert_eq!(keys, Ok(vec![String::from("p:1")]));
+    cache.remove(&[String::from("p:1")]).await.unwrap();
+    assert_eq!(cache.get("p:1").await, Err(CacheError::Miss));
+  }
+
+  /// A backend out of reach, counting how often it is asked.
+  struct Unreachable(AtomicU64);
+
+  #[rocket::async_trait]
+  impl CacheBackend for Unreachable {
+    async fn get(&self, _key: &str) -> Result<SharedBytes, CacheError> {
+      self.0.fetch_add(1, Ordering::SeqCst);
+      Err(CacheError::Unavailable(String::from("connection refused")))
+    }
+    async fn set(&self, _key: &str, _val: &[u8], _ttl: u64) -> Result<(), CacheError> {
+      self.0.fetch_add(1, Ordering::SeqCst);
+      Err(CacheError::Unavailable(String::from("connection refused")))
+    }
+    async fn keys_starting_with(&self, _prefixes: &[String]) -> Result<Vec<String>, CacheError> {
+      Ok(Vec::new())
+    }
+    async fn remove(&self, _keys: &[String]) -> Result<usize, CacheError> {
+      Ok(0)
+    }
+    async fn describe(&self, _key: &str) -> Result<CachedEntry, CacheError> {
+      Err(CacheError::Miss)
+    }
+    async fn ping(&self) -> Result<(), CacheError> {
+      Err(CacheError::Unavailable(String::from("connection refused")))
+    }
+  }
+
+  #[rocket::async_test]
+  async fn an_unreachable_backend_is_skipped_once_the_circuit_opens() {
+    let backend = Arc::new(Unreachable(AtomicU64::new(0)));
+    let cache = PaperCache {
+      backend: backend.clone(),
+      hot: None,
+      breaker: Arc::new(CircuitBreaker::new(3, Duration::from_secs(60))),
+      timeout: None,
+    };
+    for _ in 0..3 {
+      assert!(matches!(
+        cache.get("p:1").await,
+        Err(CacheError::Unavailable(_))
+      ));
+    }
+    assert_eq!(cache.breaker_stats().state, "open");
+    assert_eq!(cache.get("p:1").await, Err(CacheError::CircuitOpen));
+    assert_eq!(
+      cache.set("p:1", b"fp\n", 0).await,
+      Err(CacheError::CircuitOpen)
+    );
+    // (the requests since have not reached it)
+    assert_eq!(backend.0.load(Ordering::SeqCst), 3);
+  }
+
+  #[test]
+  fn redis_patterns_match_prefixes_literally() {
+    assert_eq!(escape_glob("a*b?[c]"), "a\\*b\\?\\[c\\]");
+  }
+
+  #[rocket::async_test]
+  async fn disk_cache_stores_shared_contents_once() {
+    let dir = std::env::temp_dir().join(format!("ar5iv_disk_cache_test_{}", std::process::id()));
+    let cache = DiskCache::new(&dir);
+    cache
+      .set("a:1/LaTeXML.css", b"fp\nbody {}", 0)
+      .await
+      .unwrap();
+    cache
+      .set("a:2/LaTeXML.css", b"fp\nbody {}", 3600)
+      .await
+      .unwrap();
+    assert_eq!(
+      &*cache.get("a:1/LaTeXML.css").await.unwrap(),
+      b"fp\nbody {}"
+    );
+    let objects = fs::read_dir(dir.join("objects")).unwrap().count();
+    assert_eq!(objects, 1);
+
+    let entry = cache.describe("a:2/LaTeXML.css").await.unwrap();
+    assert_eq!(entry.fingerprint, "fp");
+    assert!(entry.ttl > 3500);
+    let mut keys = cache
+      .keys_starting_with(&[String::from("a:")])
+      .await
+      .unwrap();
+    keys.sort();
+    assert_eq!(keys, ["a:1/LaTeXML.css", "a:2/LaTeXML.css"]);
+
+    assert_eq!(
+      cache.remove(&[String::from("a:1/LaTeXML.css")]).await,
+      Ok(1)
+    );
+    assert_eq!(cache.get("a:1/LaTeXML.css").await, Err(CacheError::Miss));
+    assert!(cache.get("a:2/LaTeXML.css").await.is_ok());
+    fs::remove_dir_all(&dir).unwrap();
+  }
+}
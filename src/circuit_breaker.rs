@@ -0,0 +1,145 @@
+use rocket::serde::Serialize;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Counts the consecutive outages of a backend, and opens after `threshold`
+/// of them: callers then skip the backend outright -- rather than each paying
+/// for a timeout -- until a probe made in the background finds it back, and
+/// closes the circuit.
+pub struct CircuitBreaker {
+  threshold: u32,
+  retry: Duration,
+  state: Mutex<BreakerState>,
+}
+
+#[derive(Default)]
+struct BreakerState {
+  failures: u32,
+  open_since: Option<Instant>,
+  trips: u64,
+  last_error: Option<String>,
+}
+
+/// The state of a breaker, as reported to operators.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct BreakerStats {
+  /// `closed` or `open`.
+  pub state: &'static str,
+  pub consecutive_failures: u32,
+  /// How long the circuit has been open, in seconds.
+  pub open_for: Option<u64>,
+  /// How many times it opened since launch.
+  pub trips: u64,
+  pub last_error: Option<String>,
+}
+
+impl CircuitBreaker {
+  pub fn new(threshold: u32, retry: Duration) -> Self {
+    CircuitBreaker {
+      threshold: threshold.max(1),
+      retry,
+      state: Mutex::new(BreakerState::default()),
+    }
+  }
+
+  /// How long to wait between probes of an open circuit.
+  pub fn retry(&self) -> Duration {
+    self.retry
+  }
+
+  pub fn is_open(&self) -> bool {
+    self
+      .state
+      .lock()
+      .is_ok_and(|state| state.open_since.is_some())
+  }
+
+  pub fn record_success(&self) {
+    if let Ok(mut state) = self.state.lock() {
+      state.failures = 0;
+    }
+  }
+
+  /// Counts an outage; true when it opened the circuit -- the caller is then
+  /// to probe the backend until it can `close` it.
+  pub fn record_failure(&self, error: &str) -> bool {
+    let Ok(mut state) = self.state.lock() else {
+      return false;
+    };
+    state.failures = state.failures.saturating_add(1);
+    state.last_error = Some(error.to_string());
+    if state.open_since.is_none() && state.failures >= self.threshold {
+      state.open_since = Some(Instant::now());
+      state.trips += 1;
+      true
+    } else {
+      false
+    }
+  }
+
+  pub fn close(&self) {
+    if let Ok(mut state) = self.state.lock() {
+      state.failures = 0;
+      state.open_since = None;
+    }
+  }
+
+  pub fn stats(&self) -> BreakerStats {
+    let Ok(state) = self.state.lock() else {
+      return BreakerStats {
+        state: "closed",
+        consecutive_failures: 0,
+        open_for: None,
+        trips: 0,
+        last_error: None,
+      };
+    };
+    BreakerStats {
+      state: if state.open_since.is_some() {
+        "open"
+      } else {
+        "closed"
+      },
+      consecutive_failures: state.failures,
+      open_for: state.open_since.map(|since| since.elapsed().as_secs()),
+      trips: state.trips,
+      last_error: state.last_error.clone(),
+    }
+  }
+}
+
+impl Default for CircuitBreaker {
+  fn default() -> Self {
+    CircuitBreaker::new(5, Duration::from_secs(5))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn opens_after_consecutive_failures_until_closed() {
+    let breaker = CircuitBreaker::new(3, Duration::from_secs(1));
+    assert!(!breaker.record_failure("refused"));
+    assert!(!breaker.record_failure("refused"));
+    // a success in between starts the count over
+    breaker.record_success();
+    assert!(!breaker.record_failure("refused"));
+    assert!(!breaker.record_failure("refused"));
+    assert!(!breaker.is_open());
+    assert!(breaker.record_failure("timed out"));
+    assert!(breaker.is_open());
+    // (only the failure opening it asks for a probe)
+    assert!(!breaker.record_failure("timed out"));
+    let stats = breaker.stats();
+    assert_eq!(stats.state, "open");
+    assert_eq!(stats.trips, 1);
+    assert_eq!(stats.last_error.as_deref(), Some("timed out"));
+
+    breaker.close();
+    assert!(!breaker.is_open());
+    assert_eq!(breaker.stats().consecutive_failures, 0);
+  }
+}
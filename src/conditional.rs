@@ -0,0 +1,544 @@
+use rocket::http::{ContentType, Status};
+use rocket::response::{self, Responder, Response};
+use rocket::tokio::fs::File as AsyncFile;
+use rocket::tokio::io::{AsyncRead, AsyncSeek, ReadBuf};
+use rocket::Request;
+use std::fs::{File, Metadata};
+use std::io::{self, Cursor, Seek, SeekFrom};
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::cache_backend::SharedBytes;
+use crate::zip_stream::stream_zip_entry;
+
+/// The validators of a served representation: an `ETag` and a `Last-Modified`
+/// date, both derived from the file on disk it was read from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Validators {
+  pub etag: String,
+  pub last_modified: SystemTime,
+}
+
+impl Validators {
+  /// For a file served as-is, e.g. a source ZIP: its mtime and size.
+  pub fn of_file(metadata: &Metadata) -> Self {
+    let last_modified = whole_seconds(metadata.modified().unwrap_or(UNIX_EPOCH));
+    Validators {
+      etag: format!("\"{:x}-{:x}\"", unix_seconds(last_modified), metadata.len()),
+      last_modified,
+    }
+  }
+
+  /// For an entry of a result bundle: the bundle's mtime and size, plus the
+  /// entry's CRC-32 -- so that a reprocessed bundle invalidates all of its
+  /// assets, but assets are told apart within one bundle.
+  pub fn of_bundle_entry(bundle: &Metadata, crc32: u32) -> Self {
+    let Validators {
+      etag,
+      last_modified,
+    } = Validators::of_file(bundle);
+    Validators {
+      etag: format!("{}-{crc32:08x}\"", etag.trim_end_matches('"')),
+      last_modified,
+    }
+  }
+
+  /// `If-None-Match`: a `*` or a list of (possibly weak) entity tags,
+  /// compared weakly.
+  fn matches_any(&self, if_none_match: &str) -> bool {
+    if_none_match.trim() == "*"
+      || if_none_match
+        .split(',')
+        .map(|tag| tag.trim().trim_start_matches("W/"))
+        .any(|tag| tag == self.etag)
+  }
+
+  /// `If-Modified-Since` (and the date form of `If-Range`)
+  fn unmodified_since(&self, date: &str) -> bool {
+    httpdate::parse_http_date(date.trim()).is_ok_and(|since| self.last_modified <= since)
+  }
+
+  /// `If-Range`: a strong entity tag, or a date.
+  fn still_current(&self, if_range: &str) -> bool {
+    let if_range = if_range.trim();
+    if if_range.starts_with('"') {
+      if_range == self.etag
+    } else if if_range.starts_with("W/") {
+      false
+    } else {
+      httpdate::parse_http_date(if_range).is_ok_and(|date| self.last_modified == date)
+    }
+  }
+}
+
+/// HTTP dates have a resolution of seconds.
+fn whole_seconds(time: SystemTime) -> SystemTime {
+  UNIX_EPOCH + Duration::from_secs(unix_seconds(time))
+}
+fn unix_seconds(time: SystemTime) -> u64 {
+  time
+    .duration_since(UNIX_EPOCH)
+    .map(|since| since.as_secs())
+    .unwrap_or_default()
+}
+
+/// The conditional and range headers of a request.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Preconditions<'a> {
+  pub if_none_match: Option<&'a str>,
+  pub if_modified_since: Option<&'a str>,
+  pub range: Option<&'a str>,
+  pub if_range: Option<&'a str>,
+}
+
+/// How to answer a request for a representation of a given length.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+  /// 304, the client's copy is current
+  NotModified,
+  /// 200, the whole representation
+  Full,
+  /// 206, the bytes `start..=end`
+  Partial(u64, u64),
+  /// 416, no requested byte exists
+  Unsatisfiable,
+}
+
+impl<'a> Preconditions<'a> {
+  pub fn of(request: &'a Request<'_>) -> Self {
+    let headers = request.headers();
+    Preconditions {
+      if_none_match: headers.get_one("If-None-Match"),
+      if_modified_since: headers.get_one("If-Modified-Since"),
+      range: headers.get_one("Range"),
+      if_range: headers.get_one("If-Range"),
+    }
+  }
+
+  pub fn evaluate(&self, validators: &Validators, len: u64) -> Outcome {
+    // If-Modified-Since only counts when there is no If-None-Match (RFC 9110, 13.1.3)
+    let not_modified = match (self.if_none_match, self.if_modified_since) {
+      (Some(if_none_match), _) => validators.matches_any(if_none_match),
+      (None, Some(since)) => validators.unmodified_since(since),
+      (None, None) => false,
+    };
+    if not_modified {
+      return Outcome::NotModified;
+    }
+    // a stale If-Range asks for the whole, current representation instead
+    let range_applies = self
+      .if_range
+      .is_none_or(|if_range| validators.still_current(if_range));
+    match self.range {
+      Some(range) if range_applies => byte_range(range, len),
+      _ => Outcome::Full,
+    }
+  }
+}
+
+/// A `Range: bytes=...` header. Only single ranges are served partially;
+/// anything else (multiple ranges, other units, syntax errors) is answered
+/// with the whole representation, as RFC 9110 allows.
+fn byte_range(range: &str, len: u64) -> Outcome {
+  let Some(spec) = range.trim().strip_prefix("bytes=") else {
+    return Outcome::Full;
+  };
+  let Some((first, last)) = spec.trim().split_once('-') else {
+    return Outcome::Full;
+  };
+  if spec.contains(',') {
+    return Outcome::Full;
+  }
+  let (first, last) = (first.trim(), last.trim());
+  if first.is_empty() {
+    // a suffix: the last N bytes
+    return match last.parse::<u64>() {
+      Ok(0) => Outcome::Unsatisfiable,
+      Ok(_) if len == 0 => Outcome::Unsatisfiable,
+      Ok(suffix) => Outcome::Partial(len.saturating_sub(suffix), len - 1),
+      Err(_) => Outcome::Full,
+    };
+  }
+  let Ok(start) = first.parse::<u64>() else {
+    return Outcome::Full;
+  };
+  let end = if last.is_empty() {
+    u64::MAX
+  } else {
+    match last.parse::<u64>() {
+      Ok(end) if end >= start => end,
+      _ => return Outcome::Full,
+    }
+  };
+  if start >= len {
+    Outcome::Unsatisfiable
+  } else {
+    Outcome::Partial(start, end.min(len - 1))
+  }
+}
+
+/// The body of a `Ranged` response: bytes in memory (shared with the cache), a
+/// file on disk, or a ZIP entry too large to buffer -- a part of its bundle's
+/// file when stored as is, else streamed from the bundle.
+pub enum RangedBody {
+  Bytes(SharedBytes),
+  File(File, u64),
+  /// `len` bytes of a file positioned at `start`.
+  FilePart {
+    file: File,
+    start: u64,
+    len: u64,
+  },
+  ZipEntry {
+    bundle: PathBuf,
+    name: String,
+    len: u64,
+  },
+}
+
+impl RangedBody {
+  fn len(&self) -> u64 {
+    match self {
+      RangedBody::Bytes(bytes) => bytes.len() as u64,
+      RangedBody::File(_, len) => *len,
+      RangedBody::FilePart { len, .. } => *len,
+      RangedBody::ZipEntry { len, .. } => *len,
+    }
+  }
+}
+
+/// Serves a representation with `ETag`/`Last-Modified` validators, answering
+/// conditional requests with 304 and `Range` requests with 206.
+pub struct Ranged {
+  pub content_type: ContentType,
+  pub body: RangedBody,
+  pub validators: Validators,
+}
+
+impl Ranged {
+  pub fn bytes(content_type: ContentType, bytes: SharedBytes, validators: Validators) -> Self {
+    Ranged {
+      content_type,
+      body: RangedBody::Bytes(bytes),
+      validators,
+    }
+  }
+
+  pub fn file(content_type: ContentType, file: File, metadata: &Metadata) -> Self {
+    Ranged {
+      content_type,
+      body: RangedBody::File(file, metadata.len()),
+      validators: Validators::of_file(metadata),
+    }
+  }
+
+  pub fn file_part(
+    content_type: ContentType,
+    file: File,
+    start: u64,
+    len: u64,
+    validators: Validators,
+  ) -> Self {
+    Ranged {
+      content_type,
+      body: RangedBody::FilePart { file, start, len },
+      validators,
+    }
+  }
+
+  pub fn zip_entry(
+    content_type: ContentType,
+    bundle: PathBuf,
+    name: String,
+    len: u64,
+    validators: Validators,
+  ) -> Self {
+    Ranged {
+      content_type,
+      body: RangedBody::ZipEntry { bundle, name, len },
+      validators,
+    }
+  }
+}
+
+impl<'r> Responder<'r, 'static> for Ranged {
+  fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+    let len = self.body.len();
+    let mut response = Response::build();
+    response
+      .raw_header("ETag", self.validators.etag.clone())
+      .raw_header(
+        "Last-Modified",
+        httpdate::fmt_http_date(self.validators.last_modified),
+      )
+      .raw_header("Accept-Ranges", "bytes")
+      // the content type is deliberate (see `sniff`); browsers mustn't second-guess it
+      .raw_header("X-Content-Type-Options", "nosniff");
+    match Preconditions::of(req).evaluate(&self.validators, len) {
+      Outcome::NotModified => response.status(Status::NotModified).ok(),
+      Outcome::Unsatisfiable => response
+        .status(Status::RangeNotSatisfiable)
+        .raw_header("Content-Range", format!("bytes */{len}"))
+        .ok(),
+      Outcome::Full => {
+        response.header(self.content_type);
+        match self.body {
+          RangedBody::Bytes(bytes) => response.sized_body(bytes.len(), Cursor::new(bytes)),
+          RangedBody::File(file, len) => {
+            response.sized_body(len as usize, AsyncFile::from_std(file))
+          }
+          RangedBody::FilePart { file, start, len } => {
+            let part = FileRange::new(AsyncFile::from_std(file), start, len);
+            response.sized_body(len as usize, part)
+          }
+          RangedBody::ZipEntry { bundle, name, len } => {
+            response.sized_body(len as usize, stream_zip_entry(bundle, name, 0, len))
+          }
+        };
+        response.ok()
+      }
+      Outcome::Partial(start, end) => {
+        let part_len = end - start + 1;
+        response
+          .status(Status::PartialContent)
+          .header(self.content_type)
+          .raw_header("Content-Range", format!("bytes {start}-{end}/{len}"));
+        match self.body {
+          RangedBody::Bytes(bytes) => {
+            let part = bytes.slice(start as usize..end as usize + 1);
+            response.sized_body(part.len(), Cursor::new(part));
+          }
+          RangedBody::File(mut file, _) => {
+            // (a seek is a cheap, non-blocking syscall)
+            file
+              .seek(SeekFrom::Start(start))
+              .map_err(|_| Status::InternalServerError)?;
+            let part = FileRange::new(AsyncFile::from_std(file), start, part_len);
+            response.sized_body(part_len as usize, part);
+          }
+          RangedBody::FilePart {
+            mut file,
+            start: offset,
+            ..
+          } => {
+            let start = offset + start;
+            file
+              .seek(SeekFrom::Start(start))
+              .map_err(|_| Status::InternalServerError)?;
+            let part = FileRange::new(AsyncFile::from_std(file), start, part_len);
+            response.sized_body(part_len as usize, part);
+          }
+          RangedBody::ZipEntry { bundle, name, .. } => {
+            let part = stream_zip_entry(bundle, name, start, part_len);
+            response.sized_body(part_len as usize, part);
+          }
+        }
+        response.ok()
+      }
+    }
+  }
+}
+
+/// A byte range of a file, positioned at its start, as a seekable body.
+pub struct FileRange {
+  file: AsyncFile,
+  start: u64,
+  len: u64,
+  pos: u64,
+}
+
+impl FileRange {
+  /// `file` must already be positioned at `start`.
+  pub fn new(file: AsyncFile, start: u64, len: u64) -> Self {
+    FileRange {
+      file,
+      start,
+      len,
+      pos: 0,
+    }
+  }
+}
+
+impl AsyncRead for FileRange {
+  fn poll_read(
+    self: Pin<&mut Self>,
+    cx: &mut Context<'_>,
+    buf: &mut ReadBuf<'_>,
+  ) -> Poll<io::Result<()>> {
+    let this = self.get_mut();
+    let remaining = this.len.saturating_sub(this.pos);
+    if remaining == 0 {
+      return Poll::Ready(Ok(()));
+    }
+    if remaining >= buf.remaining() as u64 {
+      let filled = buf.filled().len();
+      ready!(Pin::new(&mut this.file).poll_read(cx, buf))?;
+      this.pos += (buf.filled().len() - filled) as u64;
+    } else {
+      // the tail of the range: don't read past its end
+      let mut tail = vec![0; remaining as usize];
+      let mut tail_buf = ReadBuf::new(&mut tail);
+      ready!(Pin::new(&mut this.file).poll_read(cx, &mut tail_buf))?;
+      buf.put_slice(tail_buf.filled());
+      this.pos += tail_buf.filled().len() as u64;
+    }
+    Poll::Ready(Ok(()))
+  }
+}
+
+impl AsyncSeek for FileRange {
+  fn start_seek(self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+    let this = self.get_mut();
+    let target = match position {
+      SeekFrom::Start(offset) => Some(offset),
+      SeekFrom::End(offset) => this.len.checked_add_signed(offset),
+      SeekFrom::Current(offset) => this.pos.checked_add_signed(offset),
+    };
+    match target {
+      Some(target) => Pin::new(&mut this.file).start_seek(SeekFrom::Start(this.start + target)),
+      None => Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "seek before the start of the range",
+      )),
+    }
+  }
+
+  fn poll_complete(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+    let this = self.get_mut();
+    let position = ready!(Pin::new(&mut this.file).poll_complete(cx))?;
+    this.pos = position.saturating_sub(this.start);
+    Poll::Ready(Ok(this.pos))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn validators() -> Validators {
+    Validators {
+      etag: String::from("\"5f5e100-400-0a1b2c3d\""),
+      last_modified: UNIX_EPOCH + Duration::from_secs(100_000_000),
+    }
+  }
+
+  #[test]
+  fn matching_validators_are_not_modified() {
+    let validators = validators();
+    let date = httpdate::fmt_http_date(validators.last_modified);
+    for preconditions in [
+      Preconditions {
+        if_none_match: Some("\"5f5e100-400-0a1b2c3d\""),
+        ..Default::default()
+      },
+      Preconditions {
+        if_none_match: Some("\"other\", W/\"5f5e100-400-0a1b2c3d\""),
+        ..Default::default()
+      },
+      Preconditions {
+        if_none_match: Some("*"),
+        ..Default::default()
+      },
+      Preconditions {
+        if_modified_since: Some(&date),
+        ..Default::default()
+      },
+    ] {
+      assert_eq!(
+        preconditions.evaluate(&validators, 1024),
+        Outcome::NotModified
+      );
+    }
+    // a stale tag wins over a current date
+    let stale = Preconditions {
+      if_none_match: Some("\"stale\""),
+      if_modified_since: Some(&date),
+      ..Default::default()
+    };
+    assert_eq!(stale.evaluate(&validators, 1024), Outcome::Full);
+    let earlier = httpdate::fmt_http_date(validators.last_modified - Duration::from_secs(1));
+    let modified = Preconditions {
+      if_modified_since: Some(&earlier),
+      ..Default::default()
+    };
+    assert_eq!(modified.evaluate(&validators, 1024), Outcome::Full);
+  }
+
+  #[test]
+  fn byte_ranges_are_resolved() {
+    assert_eq!(byte_range("bytes=0-99", 1024), Outcome::Partial(0, 99));
+    assert_eq!(
+      byte_range("bytes=1000-", 1024),
+      Outcome::Partial(1000, 1023)
+    );
+    assert_eq!(
+      byte_range("bytes=1000-5000", 1024),
+      Outcome::Partial(1000, 1023)
+    );
+    assert_eq!(byte_range("bytes=-24", 1024), Outcome::Partial(1000, 1023));
+    assert_eq!(byte_range("bytes=-5000", 1024), Outcome::Partial(0, 1023));
+    assert_eq!(byte_range("bytes=1024-", 1024), Outcome::Unsatisfiable);
+    assert_eq!(byte_range("bytes=-0", 1024), Outcome::Unsatisfiable);
+    // served whole
+    assert_eq!(byte_range("bytes=0-9,20-29", 1024), Outcome::Full);
+    assert_eq!(byte_range("bytes=9-0", 1024), Outcome::Full);
+    assert_eq!(byte_range("items=0-9", 1024), Outcome::Full);
+    assert_eq!(byte_range("bytes=abc", 1024), Outcome::Full);
+  }
+
+  #[test]
+  fn ranges_only_apply_to_the_current_representation() {
+    let validators = validators();
+    let current = Preconditions {
+      range: Some("bytes=0-9"),
+      if_range: Some("\"5f5e100-400-0a1b2c3d\""),
+      ..Default::default()
+    };
+    assert_eq!(current.evaluate(&validators, 1024), Outcome::Partial(0, 9));
+    let stale = Preconditions {
+      if_range: Some("\"stale\""),
+      ..current
+    };
+    assert_eq!(stale.evaluate(&validators, 1024), Outcome::Full);
+    let date = httpdate::fmt_http_date(validators.last_modified);
+    let dated = Preconditions {
+      if_range: Some(&date),
+      ..current
+    };
+    assert_eq!(dated.evaluate(&validators, 1024), Outcome::Partial(0, 9));
+  }
+
+  #[rocket::async_test]
+  async fn file_ranges_stop_at_their_end() {
+    use rocket::tokio::io::{AsyncReadExt, AsyncSeekExt};
+    let path = std::env::temp_dir().join(format!("ar5iv_file_range_test_{}", std::process::id()));
+    std::fs::write(&path, (0u8..=255).collect::<Vec<u8>>()).unwrap();
+    let mut file = File::open(&path).unwrap();
+    file.seek(SeekFrom::Start(10)).unwrap();
+    let mut range = FileRange::new(AsyncFile::from_std(file), 10, 20);
+    let mut out = Vec::new();
+    range.read_to_end(&mut out).await.unwrap();
+    assert_eq!(out, (10u8..30).collect::<Vec<u8>>());
+    assert_eq!(range.seek(SeekFrom::Start(5)).await.unwrap(), 5);
+    out.clear();
+    range.read_to_end(&mut out).await.unwrap();
+    assert_eq!(out, (15u8..30).collect::<Vec<u8>>());
+    assert_eq!(range.seek(SeekFrom::End(-2)).await.unwrap(), 18);
+    std::fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn bundle_entries_extend_the_bundle_tag() {
+    let metadata = std::fs::metadata("Cargo.toml").unwrap();
+    let file = Validators::of_file(&metadata);
+    let entry = Validators::of_bundle_entry(&metadata, 0x0a1b2c3d);
+    assert!(file.etag.starts_with('"') && file.etag.ends_with('"'));
+    assert_eq!(
+      entry.etag,
+      format!("{}-0a1b2c3d\"", file.etag.trim_end_matches('"'))
+    );
+    assert_eq!(entry.last_modified, file.last_modified);
+  }
+}
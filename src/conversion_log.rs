@@ -0,0 +1,424 @@
+use crate::assemble_asset::LatexmlStatus;
+use regex::Regex;
+use rocket::serde::Serialize;
+use std::sync::LazyLock;
+
+static SOURCE_LOCATION: LazyLock<Regex> =
+  LazyLock::new(|| Regex::new(r"^at (.+?); line (\d+) col (\d+)").unwrap());
+static SUMMARY_COUNT: LazyLock<Regex> =
+  LazyLock::new(|| Regex::new(r"^(\d+) ([a-z ]+?)s?(?:\[(.*)\])?$").unwrap());
+
+/// The severity of a single latexml message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(crate = "rocket::serde", rename_all = "lowercase")]
+pub enum Severity {
+  Info,
+  Warning,
+  Error,
+  Fatal,
+}
+impl Severity {
+  /// The latexml CSS class the HTML conversion report uses for this severity.
+  pub fn as_ltx_class(&self) -> &'static str {
+    match self {
+      Severity::Info => "ltx_INFO",
+      Severity::Warning => "ltx_WARNING",
+      Severity::Error => "ltx_ERROR",
+      Severity::Fatal => "ltx_FATAL",
+    }
+  }
+  /// The conversion status a message of this severity implies.
+  pub fn as_status(&self) -> LatexmlStatus {
+    match self {
+      Severity::Info => LatexmlStatus::Ok,
+      Severity::Warning => LatexmlStatus::Warning,
+      Severity::Error => LatexmlStatus::Error,
+      Severity::Fatal => LatexmlStatus::Fatal,
+    }
+  }
+}
+
+/// Where in the TeX sources a message was raised.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct SourceLocation {
+  pub file: String,
+  pub line: usize,
+  pub column: usize,
+}
+
+/// One latexml message, e.g.
+/// ```text
+/// Error:undefined:\foo The token T_CS[\foo] is not defined.
+///   at paper.tex; line 12 col 15 - line 12 col 19
+/// ```
+/// `category` is the message class (`undefined`, `missing_file`, `expected`,
+/// ...) and `object` what it is about (a macro, a package, a file, ...).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct LogEntry {
+  pub severity: Severity,
+  pub category: String,
+  pub object: String,
+  pub message: String,
+  pub location: Option<SourceLocation>,
+  /// the indented lines following the message, trimmed
+  pub details: Vec<String>,
+}
+impl LogEntry {
+  /// The message line, as latexml printed it.
+  pub fn headline(&self) -> String {
+    let severity = match self.severity {
+      Severity::Info => "Info",
+      Severity::Warning => "Warning",
+      Severity::Error => "Error",
+      Severity::Fatal => "Fatal",
+    };
+    if self.category.is_empty() {
+      format!("{severity}: {}", self.message)
+    } else if self.message.is_empty() {
+      format!("{severity}:{}:{}", self.category, self.object)
+    } else {
+      format!(
+        "{severity}:{}:{} {}",
+        self.category, self.object, self.message
+      )
+    }
+  }
+}
+
+/// The final tally latexml prints, e.g.
+/// `Conversion complete: 2 warnings; 1 error; 1 undefined macro[\foo]`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ConversionSummary {
+  pub warnings: usize,
+  pub errors: usize,
+  pub fatal: usize,
+  pub undefined_macros: Vec<String>,
+  pub missing_files: Vec<String>,
+}
+impl ConversionSummary {
+  /// The worst problem the tally counts.
+  pub fn severity(&self) -> Severity {
+    if self.fatal > 0 {
+      Severity::Fatal
+    } else if self.errors > 0 {
+      Severity::Error
+    } else if self.warnings > 0 {
+      Severity::Warning
+    } else {
+      Severity::Info
+    }
+  }
+}
+
+/// A line of the report, in order, as its HTML view renders it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReportLine {
+  /// `entries[i]`, with its details
+  Entry(usize),
+  /// a `Conversion complete:` or `Post-processing complete:` tally, and the
+  /// worst problem it counts
+  Tally(String, Severity),
+  /// anything else, e.g. `Status:conversion:0`
+  Text(String),
+}
+
+/// A `cortex.log` conversion report, parsed.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ConversionLog {
+  pub entries: Vec<LogEntry>,
+  /// the `Conversion complete:` tally
+  pub conversion: Option<ConversionSummary>,
+  /// the `Post-processing complete:` tally
+  pub post_processing: Option<ConversionSummary>,
+  /// the (most severe) `Status:conversion:N` line
+  pub conversion_status: Option<LatexmlStatus>,
+  /// the report's lines, in order (the HTML view's outline)
+  #[serde(skip)]
+  pub lines: Vec<ReportLine>,
+}
+
+impl ConversionLog {
+  pub fn parse(log: &str) -> Self {
+    let mut parsed = ConversionLog::default();
+    // whether indented lines still continue the last message
+    let mut in_entry = false;
+    for line in log.lines() {
+      if let Some(entry) = parse_message(line) {
+        parsed.lines.push(ReportLine::Entry(parsed.entries.len()));
+        parsed.entries.push(entry);
+        in_entry = true;
+      } else if in_entry && line.starts_with([' ', '\t']) {
+        if let Some(entry) = parsed.entries.last_mut() {
+          let detail = line.trim();
+          if entry.location.is_none() {
+            entry.location = parse_location(detail);
+          }
+          entry.details.push(detail.to_string());
+        }
+      } else {
+        in_entry = false;
+        if let Some(tally) = line.strip_prefix("Conversion complete:") {
+          let summary = parse_summary(tally);
+          parsed
+            .lines
+            .push(ReportLine::Tally(line.to_string(), summary.severity()));
+          parsed.conversion = Some(summary);
+        } else if let Some(tally) = line.strip_prefix("Post-processing complete:") {
+          let summary = parse_summary(tally);
+          parsed
+            .lines
+            .push(ReportLine::Tally(line.to_string(), summary.severity()));
+          parsed.post_processing = Some(summary);
+        } else {
+          parsed.lines.push(ReportLine::Text(line.to_string()));
+        }
+        if let Some(code) = line.strip_prefix("Status:conversion:") {
+          // (a status line truncated right after the prefix counts as fatal)
+          let status = match code.chars().next() {
+            Some('0') => LatexmlStatus::Ok,
+            Some('1') => LatexmlStatus::Warning,
+            Some('2') => LatexmlStatus::Error,
+            _ => LatexmlStatus::Fatal,
+          };
+          if parsed
+            .conversion_status
+            .as_ref()
+            .is_none_or(|s| *s < status)
+          {
+            parsed.conversion_status = Some(status);
+          }
+        }
+      }
+    }
+    parsed
+  }
+
+  /// The overall conversion status: the most severe of all messages and
+  /// status lines.
+  pub fn status(&self) -> LatexmlStatus {
+    let messages = self.entries.iter().map(|entry| entry.severity.as_status());
+    messages
+      .chain(self.conversion_status.clone())
+      .max()
+      .unwrap_or(LatexmlStatus::Ok)
+  }
+
+  /// The distinct objects named by messages of a category, in order of first
+  /// appearance -- e.g. the undefined macros for `undefined`.
+  pub fn objects_of(&self, category: &str) -> Vec<&str> {
+    let mut objects: Vec<&str> = Vec::new();
+    for entry in self.entries.iter() {
+      if entry.category == category
+        && !entry.object.is_empty()
+        && !objects.contains(&entry.object.as_str())
+      {
+        objects.push(&entry.object);
+      }
+    }
+    objects
+  }
+}
+
+/// The arXiv version of `id` a log's conversion ran on, as named by the
+/// sources it processed (e.g. `2105.04404v2/main.tex`): its first `<id>vN`.
+pub fn source_version(log: &str, id: &str) -> Option<String> {
+  log.match_indices(id).find_map(|(start, _)| {
+    let rest = log[start + id.len()..].strip_prefix('v')?;
+    let digits = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    (digits > 0).then(|| format!("v{}", &rest[..digits]))
+  })
+}
+
+fn message_severity(line: &str) -> Option<(Severity, &str)> {
+  if let Some(rest) = line.strip_prefix("Warning:") {
+    Some((Severity::Warning, rest))
+  } else if let Some(rest) = line.strip_prefix("Error:") {
+    Some((Severity::Error, rest))
+  } else if let Some(rest) = line.strip_prefix("Info:") {
+    Some((Severity::Info, rest))
+  } else {
+    line
+      .strip_prefix("Fatal:")
+      .map(|rest| (Severity::Fatal, rest))
+  }
+}
+
+/// `Severity:category:object message`, where category and object may be absent.
+fn parse_message(line: &str) -> Option<LogEntry> {
+  let (severity, rest) = message_severity(line)?;
+  let (category, rest) = match rest.split_once(':') {
+    Some((category, rest)) if !category.is_empty() && !category.contains(char::is_whitespace) => {
+      (category, rest)
+    }
+    _ => ("", rest),
+  };
+  let (object, message) = if category.is_empty() {
+    ("", rest)
+  } else {
+    rest.split_once(' ').unwrap_or((rest, ""))
+  };
+  Some(LogEntry {
+    severity,
+    category: category.to_string(),
+    object: object.to_string(),
+    message: message.trim().to_string(),
+    location: None,
+    details: Vec::new(),
+  })
+}
+
+/// `at paper.tex; line 12 col 15 - line 12 col 19`
+fn parse_location(detail: &str) -> Option<SourceLocation> {
+  let caps = SOURCE_LOCATION.captures(detail)?;
+  Some(SourceLocation {
+    file: caps[1].to_string(),
+    line: caps[2].parse().ok()?,
+    column: caps[3].parse().ok()?,
+  })
+}
+
+/// ` 2 warnings; 1 error; 2 undefined macros[\foo, \bar]; 1 missing file[x]`
+fn parse_summary(tally: &str) -> ConversionSummary {
+  let mut summary = ConversionSummary::default();
+  for part in tally.split(';') {
+    if let Some(caps) = SUMMARY_COUNT.captures(part.trim()) {
+      let count = caps[1].parse().unwrap_or_default();
+      let names = || {
+        caps
+          .get(3)
+          .map(|list| {
+            list
+              .as_str()
+              .split(',')
+              .map(|name| name.trim().to_string())
+              .filter(|name| !name.is_empty())
+              .collect()
+          })
+          .unwrap_or_default()
+      };
+      match &caps[2] {
+        "warning" => summary.warnings = count,
+        "error" => summary.errors = count,
+        "fatal error" => summary.fatal = count,
+        "undefined macro" => summary.undefined_macros = names(),
+        "missing file" => summary.missing_files = names(),
+        _ => {}
+      }
+    }
+  }
+  summary
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const LOG: &str = "processing started
+Warning:missing_file:mypackage Can't find package file mypackage.sty
+\tat paper.tex; line 3 col 0 - line 3 col 24
+\tAnticipate undefined macros or environments
+Error:undefined:\\foo The token T_CS[\\foo] is not defined.
+\tat paper.tex; line 12 col 15 - line 12 col 19
+Info:note:hint Some info
+Conversion complete: 1 warning; 2 errors; 1 undefined macro[\\foo]; 1 missing file[mypackage]
+Status:conversion:2
+";
+
+  #[test]
+  fn messages_are_typed() {
+    let log = ConversionLog::parse(LOG);
+    assert_eq!(log.entries.len(), 3);
+    let missing = &log.entries[0];
+    assert_eq!(missing.severity, Severity::Warning);
+    assert_eq!(missing.category, "missing_file");
+    assert_eq!(missing.object, "mypackage");
+    assert_eq!(missing.message, "Can't find package file mypackage.sty");
+    assert_eq!(
+      missing.location,
+      Some(SourceLocation {
+        file: String::from("paper.tex"),
+        line: 3,
+        column: 0
+      })
+    );
+    assert_eq!(missing.details.len(), 2);
+    let undefined = &log.entries[1];
+    assert_eq!(undefined.severity, Severity::Error);
+    assert_eq!(undefined.object, "\\foo");
+    assert_eq!(undefined.location.as_ref().map(|l| l.line), Some(12));
+    assert_eq!(log.entries[2].category, "note");
+    assert_eq!(log.objects_of("undefined"), vec!["\\foo"]);
+  }
+
+  #[test]
+  fn conversion_tally_is_counted() {
+    let log = ConversionLog::parse(LOG);
+    let summary = log.conversion.clone().unwrap();
+    assert_eq!(summary.warnings, 1);
+    assert_eq!(summary.errors, 2);
+    assert_eq!(summary.fatal, 0);
+    assert_eq!(summary.undefined_macros, vec!["\\foo"]);
+    assert_eq!(summary.missing_files, vec!["mypackage"]);
+    assert_eq!(log.conversion_status, Some(LatexmlStatus::Error));
+    assert_eq!(log.status(), LatexmlStatus::Error);
+  }
+
+  #[test]
+  fn uncategorized_messages_keep_their_text() {
+    let log = ConversionLog::parse("Warning: x\nStatus:conversion:0");
+    assert_eq!(log.entries[0].category, "");
+    assert_eq!(log.entries[0].message, "x");
+    assert_eq!(log.status(), LatexmlStatus::Warning);
+  }
+
+  #[test]
+  fn source_version_is_named_by_the_log() {
+    let log = "processing started 2105.04404.zip\n(Loading 2105.04404v2/main.tex)";
+    assert_eq!(source_version(log, "2105.04404"), Some(String::from("v2")));
+    assert_eq!(source_version(LOG, "2105.04404"), None);
+  }
+
+  #[test]
+  fn report_lines_keep_their_order() {
+    let log = ConversionLog::parse(LOG);
+    assert_eq!(log.lines.len(), 6);
+    assert_eq!(
+      log.lines[0],
+      ReportLine::Text(String::from("processing started"))
+    );
+    assert_eq!(log.lines[1], ReportLine::Entry(0));
+    assert_eq!(log.lines[3], ReportLine::Entry(2));
+    assert!(matches!(
+      log.lines[4],
+      ReportLine::Tally(_, Severity::Error)
+    ));
+    assert_eq!(
+      log.entries[1].headline(),
+      "Error:undefined:\\foo The token T_CS[\\foo] is not defined."
+    );
+  }
+
+  #[test]
+  fn tallies_are_highlighted_by_their_worst_count() {
+    let tally = |line| match ConversionLog::parse(line).lines.pop() {
+      Some(ReportLine::Tally(_, severity)) => Some(severity),
+      _ => None,
+    };
+    assert_eq!(
+      tally("Conversion complete: 1 fatal error; 2 errors"),
+      Some(Severity::Fatal)
+    );
+    assert_eq!(
+      tally("Post-processing complete: 1 warning"),
+      Some(Severity::Warning)
+    );
+    assert_eq!(
+      tally("Conversion complete: No obvious problems"),
+      Some(Severity::Info)
+    );
+  }
+}
@@ -1,14 +1,17 @@
 use crate::assemble_asset::LatexmlStatus;
+use crate::assemble_error::AssembleError;
+use crate::asset_urls::AssetUrls;
 use crate::constants::{document_css_urls, DOC_NOT_FOUND_TEMPLATE, SITE_CSS_URL};
-use regex::{Captures, Regex};
-use std::borrow::Cow;
+use crate::conversion_log::{ConversionLog, ReportLine, Severity};
+use lol_html::errors::RewritingError;
+use lol_html::html_content::{ContentType, Element};
+use lol_html::{element, end_tag, text, HandlerResult, HtmlRewriter, Settings};
+use regex::Regex;
+use std::cell::RefCell;
+use std::rc::Rc;
 use std::sync::LazyLock;
 use unicode_segmentation::UnicodeSegmentation;
 
-static END_HEAD: LazyLock<Regex> = LazyLock::new(|| Regex::new("</head>").unwrap());
-static END_BODY: LazyLock<Regex> = LazyLock::new(|| Regex::new("</body>").unwrap());
-static START_FOOTER: LazyLock<Regex> =
-  LazyLock::new(|| Regex::new("<footer class=\"ltx_page_footer\">").unwrap());
 static TITLE_ELEMENT: LazyLock<Regex> =
   LazyLock::new(|| Regex::new("<title>((?s)[^<]+?)</title>").unwrap());
 static SINFUL_P_CONTENT: LazyLock<Regex> =
@@ -17,117 +20,131 @@ static SINFUL_MATH: LazyLock<Regex> = LazyLock::new(|| Regex::new("<math(?s:.+?)
 static SINFUL_TAGS: LazyLock<Regex> = LazyLock::new(|| Regex::new("<[^>]+?>").unwrap());
 static ABSTRACT_ELEMENT: LazyLock<Regex> =
   LazyLock::new(|| Regex::new("\"ltx_abstract\">((?s).+?)</div>").unwrap());
-static SRC_ATTR: LazyLock<Regex> = LazyLock::new(|| Regex::new(" src=\"([^\"]+)").unwrap());
-static DATA_SVG_ATTR: LazyLock<Regex> =
-  LazyLock::new(|| Regex::new(" data=\"([^\"]+)[.]svg").unwrap());
 static EXTERNAL_HREF: LazyLock<Regex> = LazyLock::new(|| Regex::new(" href=\"http").unwrap());
 
+/// The text content of a document's `<title>` element.
+pub fn title_text(html: &str) -> Option<&str> {
+  TITLE_ELEMENT
+    .captures(html)
+    .and_then(|caps| caps.get(1))
+    .map(|title| title.as_str())
+}
+
+/// The first paragraph of a document's abstract, with math and markup stripped.
+pub fn abstract_text(html: &str) -> Option<String> {
+  let abs_cap = ABSTRACT_ELEMENT.captures(html)?;
+  let p_caps = SINFUL_P_CONTENT.captures(&abs_cap[1])?;
+  let no_math = SINFUL_MATH.replace_all(&p_caps[1], "");
+  Some(SINFUL_TAGS.replace_all(&no_math, "").into_owned())
+}
+
 /// Escape a fragment for use inside a double-quoted HTML attribute value.
 fn attr_escape(value: &str) -> String {
   value.replace('"', "&quot;")
 }
 
-pub fn dirty_branded_ar5iv_html(
-  mut main_content: String,
-  id_arxiv: &str,
-  status: LatexmlStatus,
-  prev: Option<String>,
-  next: Option<String>,
-) -> String {
-  let status_css_class = status.as_css_class();
-  // ensure main_content is a string if undefined
-  if main_content.is_empty() {
-    main_content = DOC_NOT_FOUND_TEMPLATE.to_string();
+/// The `og:description` meta tag for a document, if it has an abstract.
+fn og_description(html: &str) -> String {
+  // *IF* we have an abstract, fish out a description, using the most sinful of regex judo
+  // YES, this is bad, but have you tried re-serializing the DOM for a 400-page book with cross-referenced MathML?
+  //      we just don't have the time.
+  // Real solution: a metadata latexml post-processor, which does the annotations as we create <title> and friends.
+  if let Some(abstract_text) = abstract_text(html) {
+    // keep it brief.
+    let text_description = if abstract_text.len() > 218 {
+      abstract_text.graphemes(true).take(218).collect::<Vec<_>>().join("") + "…"
+    } else {
+      abstract_text
+    };
+    String::from("<meta property=\"og:description\" content=\"")
+      + &attr_escape(&text_description)
+      + "\">"
   } else {
-    // Global replacements:
-    // 1. ensure we have a lang attribute otherwise, English being most common in arXiv
-    main_content = main_content.replacen("<html>", "<html lang=\"en\">", 1)
-    // 2. completely unrelated, drop all "invisible times" characters (U+2062) until
-    //    latexml's grammar starts producing them more sparingly.
-      .replace(">\u{2062}"," lspace='0px' rspace='0px'>");
-
-    // Note: replacen would be faster, but we can't access the title content
-    // .replacen("<title>", &format!("<title>[{}] ",id_arxiv), 1);
-    main_content = TITLE_ELEMENT.replace(&main_content, |caps: &Captures| {
-      // *IF* we have an abstract, fish out a description, using the most sinful of regex judo
-      // YES, this is bad, but have you tried re-serializing the DOM for a 400-page book with cross-referenced MathML?
-      //      we just don't have the time.
-      // Real solution: a metadata latexml post-processor, which does the annotations as we create <title> and friends.
-      let description = if let Some(abs_cap) = ABSTRACT_ELEMENT.captures(&main_content) {
-        if let Some(p_caps) = SINFUL_P_CONTENT.captures(&abs_cap[1]) {
-          let no_math = SINFUL_MATH.replace_all(&p_caps[1],"");
-          let no_tags = SINFUL_TAGS.replace_all(
-            &no_math,"");
-          // keep it brief.
-          let text_description = if no_tags.len() > 218 {
-            Cow::Owned(no_tags.graphemes(true).take(218).collect::<Vec<_>>().join("") +"…")
-          } else {
-            no_tags
-          };
-          String::from("<meta property=\"og:description\" content=\"") +&attr_escape(&text_description)+"\">"
-        } else {
-          String::default()
-        }
-      } else {
-        String::default()
-      };
-      // titles end up in double-quoted attribute values below -- escape them.
-      let title_attr = attr_escape(&caps[1]);
-      // 1. also add the arxiv id to the title element
-      // 2. this is also the best place to insert vendor-specific meta tags
-      String::from("<title>[")+id_arxiv+"] "+&caps[1]+"</title>"+&description+r###"
+    String::default()
+  }
+}
+
+/// The vendor-specific meta tags, inserted right after the `<title>` element.
+fn title_meta(id_arxiv: &str, title: &str, description: &str) -> String {
+  // titles end up in double-quoted attribute values below -- escape them.
+  let title_attr = attr_escape(title);
+  String::from(description)
+    + r###"
 <meta name="viewport" content="width=device-width, initial-scale=1">
 <meta name="twitter:card" content="summary">
-<meta name="twitter:title" content=""###+&title_attr+r###"">
+<meta name="twitter:title" content=""###
+    + &title_attr
+    + r###"">
 <meta name="twitter:image:src" content="https://ar5iv.labs.arxiv.org/assets/ar5iv_card.png">
 <meta name="twitter:image:alt" content="ar5iv logo">
-<meta property="og:title" content=""###+&title_attr+r###"">
+<meta property="og:title" content=""###
+    + &title_attr
+    + r###"">
 <meta property="og:site_name" content="ar5iv">
 <meta property="og:image" content="https://ar5iv.labs.arxiv.org/assets/ar5iv_card.png">
 <meta property="og:type" content="article">
-<meta property="og:url" content="https://ar5iv.labs.arxiv.org/html/"###+id_arxiv+r###"">
-<link rel="canonical" href="https://ar5iv.labs.arxiv.org/html/"### +id_arxiv+r###"">
-"### }).to_string();
-  }
+<meta property="og:url" content="https://ar5iv.labs.arxiv.org/html/"###
+    + id_arxiv
+    + r###"">
+<link rel="canonical" href="https://ar5iv.labs.arxiv.org/html/"###
+    + id_arxiv
+    + r###"">
+"###
+}
 
-  let main_content_src = SRC_ATTR.replace_all(&main_content, |caps: &Captures| {
-    // leave as-is data URL images and remote sources
-    if caps[1].starts_with("data:") || caps[1].starts_with("http") {
-      String::from(" src=\"") + &caps[1]
-    } else {
-      // there is a catch here in the ar5iv.org setting.
-      // the old ID scheme has an extra component in the relative path, e.g. compare
-      // ./astro-ph/0001016
-      // to the modern
-      // ./2105.04404
-      // so we should *always* use the id *without* the field,
-      // when pointing from within a document to an asset under it.
-      //
-      // NEW: Rather than struggle with relativistic issues, let's just do the absolute path.
-      String::from(" src=\"/html/") + id_arxiv + "/assets/" + &caps[1]
+/// A requested arXiv version we don't hold, and the one served in its place.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MissingVersion {
+  pub requested: String,
+  /// the version the served bundle was converted from (e.g. "v2"), when its
+  /// log names it
+  pub served: Option<String>,
+}
+
+/// Where a requested arXiv version is not the one we serve, say so upfront.
+fn version_notice(id_arxiv: &str, version: &MissingVersion) -> String {
+  let showing = match version.served {
+    Some(ref served) => {
+      String::from("Showing version ") + served + ", the most recent converted by ar5iv, instead"
     }
-  });
-  main_content = DATA_SVG_ATTR
-    .replace_all(&main_content_src, |caps: &Captures| {
-      if caps[1].starts_with("data:") || caps[1].starts_with("http") {
-        String::from(" data=\"") + &caps[1] + ".svg"
-      } else {
-        String::from(" data=\"/html/") + id_arxiv + "/assets/" + &caps[1] + ".svg"
-      }
-    })
-    .to_string();
-  main_content = EXTERNAL_HREF.replace_all(&main_content," target=\"_blank\" href=\"http").to_string();
+    None => String::from("Showing the most recent version converted by ar5iv instead"),
+  };
+  String::from(
+    r###"
+<div class="ar5iv-version-notice ltx_para"><p class="ltx_p"><span class="ltx_WARNING">
+ar5iv has no conversion of arXiv version "###,
+  ) + &version.requested
+    + " of this article. "
+    + &showing
+    + ", see <a target=\"_blank\" href=\"https://arxiv.org/abs/"
+    + id_arxiv
+    + &version.requested
+    + "\">"
+    + id_arxiv
+    + &version.requested
+    + r###"</a> on arXiv.
+</span></p></div>
+"###
+}
+
+/// The ar5iv navigation bar, inserted right before the latexml page footer.
+fn footer_navigation(
+  id_arxiv: &str,
+  status: &LatexmlStatus,
+  prev: Option<&str>,
+  next: Option<&str>,
+) -> String {
+  let status_css_class = status.as_css_class();
   // if this is a Fatal conversion, warn readers explicitly.
-  let status_message = if status == LatexmlStatus::Fatal {
+  let status_message = if *status == LatexmlStatus::Fatal {
     r###"
 <div class="ltx_document"><div class="ltx_para"><div class="ltx_p"><span class="ltx_ERROR">
 Conversion to HTML had a Fatal error and exited abruptly. This document may be truncated or damaged.
 </span></div></div></div>
 </article>
 "###
-      .to_string()
   } else {
-    String::new()
+    ""
   };
 
   // If a conversion log is present, attach it as a trailing section
@@ -153,7 +170,7 @@ Conversion to HTML had a Fatal error and exited abruptly. This document may be t
     // <a class="ar5iv-text-button" href="/source/"###
     //+ id_arxiv
     //+ r###".zip" class="ar5iv-text-button">Download<br>TeX&nbsp;source</a>
-  let ar5iv_footer = status_message
+  String::from(status_message)
     + "<div class=\"ar5iv-footer\">"
     + &prev_html
     + r###"
@@ -172,17 +189,19 @@ Conversion to HTML had a Fatal error and exited abruptly. This document may be t
     + r###"" class="ar5iv-text-button arxiv-ui-theme">View&nbsp;original<br>on&nbsp;arXiv</a>"###
     + &next_html
     + r###"
-</div><footer class="ltx_page_footer">
+</div>"###
+}
+
+/// The ar5iv links leading the latexml page footer.
+static FOOTER_LINKS: &str = r###"
 <a class="ar5iv-toggle-color-scheme" href="javascript:toggleColorScheme()" title="Toggle ar5iv color scheme"><span class="color-scheme-icon"></span></a>
 <a class="ar5iv-footer-button" href="https://arxiv.org/help/license" target="_blank">Copyright</a>
 <a class="ar5iv-footer-button" href="https://arxiv.org/help/policies/privacy_policy" target="_blank">Privacy Policy</a>
 "###;
-  main_content = START_FOOTER
-    .replace(&main_content, ar5iv_footer)
-    .to_string();
-  // Hide the polyfill dirty work behind a curtain
-  let active_js = concat!(
-    r###"
+
+// Hide the polyfill dirty work behind a curtain
+static ACTIVE_JS: &str = concat!(
+  r###"
     <script>
       var canMathML = typeof(MathMLElement) == "function";
       if (!canMathML) {
@@ -210,8 +229,8 @@ Conversion to HTML had a Fatal error and exited abruptly. This document may be t
               }); } } };
       }
     </script>"###,
-    // Let's experiment with an inline bibitem preview
-    r###"
+  // Let's experiment with an inline bibitem preview
+  r###"
     <script>
     // Auxiliary function, building the preview feature when
     // an inline citation is clicked
@@ -253,15 +272,17 @@ Conversion to HTML had a Fatal error and exited abruptly. This document may be t
       link.addEventListener("click", clicked_cite);
     });
     </script>
-    "###,
-    "</body>"
-  );
+    "###
+);
+
+/// The color-scheme script and the ar5iv stylesheets, closing the `<head>`.
+fn head_assets(id_arxiv: &str) -> String {
   // Recent months (see GLOWUP_ID_PREFIXES) are served the glowup ar5iv-css
   // theme; everything else keeps the default stylesheet. The site CSS is shared.
   let (fonts_css_url, ar5iv_css_url) = document_css_urls(id_arxiv);
   // Thanks to https://stackoverflow.com/questions/56300132/how-to-override-css-prefers-color-scheme-setting
   // local storage is used to override OS theme settings
-  let pre_js_and_css = String::from(r###"
+  String::from(r###"
 <script>
   function detectColorScheme(){
     var theme="light";
@@ -298,15 +319,224 @@ Conversion to HTML had a Fatal error and exited abruptly. This document may be t
   + "\"><link media=\"all\" rel=\"stylesheet\" href=\""
   + SITE_CSS_URL
   + "\">
-</head>";
+"
+}
 
-  main_content = END_HEAD.replace(&main_content, pre_js_and_css).to_string();
-  main_content = END_BODY.replace(&main_content, active_js).to_string();
-  main_content
+/// Brands a latexml document as an ar5iv article page, in a single streaming
+/// pass: asset references are pointed at `/html/<id>/assets/` and links to
+/// the other `pages` of the bundle (its main page first) at their routes (see
+/// `AssetUrls`), and the meta tags, stylesheets, scripts and ar5iv footer are
+/// woven in as the document streams by. Markup the rewriter gives up on is
+/// an error: there is no other branding to fall back to.
+pub fn dirty_branded_ar5iv_html(
+  main_content: String,
+  id_arxiv: &str,
+  pages: &[String],
+  status: LatexmlStatus,
+  prev: Option<String>,
+  next: Option<String>,
+  missing_version: Option<MissingVersion>,
+) -> Result<String, AssembleError> {
+  // ensure main_content is a string if undefined
+  let is_placeholder = main_content.is_empty();
+  let main_content = if is_placeholder {
+    DOC_NOT_FOUND_TEMPLATE.to_string()
+  } else {
+    main_content
+  };
+  let branded = stream_branding(
+    &main_content,
+    is_placeholder,
+    id_arxiv,
+    AssetUrls::new(id_arxiv).with_pages(pages),
+    &status,
+    prev.as_deref(),
+    next.as_deref(),
+    missing_version.as_ref(),
+  )
+  .map_err(|e| AssembleError::Branding(e.to_string()))?;
+  Ok(
+    String::from_utf8(branded)
+      .unwrap_or_else(|e| String::from_utf8_lossy(e.as_bytes()).into_owned()),
+  )
+}
+
+/// The `<title>` being branded: its text is only complete at its end tag.
+#[derive(Default)]
+struct TitleState {
+  in_title: bool,
+  done: bool,
+  text: String,
+}
+
+#[allow(clippy::too_many_arguments)]
+fn stream_branding(
+  main_content: &str,
+  is_placeholder: bool,
+  id_arxiv: &str,
+  asset_urls: AssetUrls,
+  status: &LatexmlStatus,
+  prev: Option<&str>,
+  next: Option<&str>,
+  missing_version: Option<&MissingVersion>,
+) -> Result<Vec<u8>, RewritingError> {
+  // the description needs the abstract, which streams by after the <title>,
+  // so it is fished out upfront (a scan, not a copy).
+  let description = if is_placeholder {
+    String::new()
+  } else {
+    og_description(main_content)
+  };
+  let title_state = Rc::new(RefCell::new(TitleState::default()));
+  let mut branded_html = false;
+  let mut branded_page_content = false;
+  let mut branded_footer = false;
+  let mut branded_head = false;
+  let mut branded_body = false;
+
+  let mut output = Vec::with_capacity(main_content.len() + 16_384);
+  let mut rewriter = HtmlRewriter::new(
+    Settings {
+      element_content_handlers: vec![
+        // ensure we have a lang attribute otherwise, English being most common in arXiv
+        element!("html", |el| {
+          if !is_placeholder && !branded_html && el.attributes().is_empty() {
+            el.set_attribute("lang", "en")?;
+          }
+          branded_html = true;
+          Ok(())
+        }),
+        // also add the arxiv id to the title element, and follow it with the
+        // vendor-specific meta tags.
+        element!("title", |el| {
+          let mut state = title_state.borrow_mut();
+          if !is_placeholder && !state.done {
+            state.in_title = true;
+            state.text.clear();
+            let title_state = Rc::clone(&title_state);
+            let id_arxiv = id_arxiv.to_string();
+            let description = description.clone();
+            el.on_end_tag(end_tag!(move |end| {
+              let mut state = title_state.borrow_mut();
+              state.in_title = false;
+              if !state.text.is_empty() {
+                state.done = true;
+                // (the canonical link is an external href like any other)
+                let meta = title_meta(&id_arxiv, &state.text, &description);
+                let meta = EXTERNAL_HREF.replace_all(&meta, " target=\"_blank\" href=\"http");
+                end.after(&meta, ContentType::Html);
+              }
+              Ok(())
+            }))?;
+          }
+          Ok(())
+        }),
+        text!("title", |chunk| {
+          let mut state = title_state.borrow_mut();
+          if state.in_title && !chunk.as_str().is_empty() {
+            if state.text.is_empty() {
+              chunk.before(&format!("[{id_arxiv}] "), ContentType::Html);
+            }
+            state.text.push_str(chunk.as_str());
+          }
+          Ok(())
+        }),
+        // leave as-is data URL images and remote sources
+        element!(
+          "[src], [href], [srcset], [poster], [data], [style*='url(']",
+          |el| asset_urls.rewrite_element(el)
+        ),
+        element!("[href^='http']", |el| {
+          insert_attribute_before(el, "href", "target", "_blank")
+        }),
+        element!("div[class='ltx_page_content']", |el| {
+          if let Some(version) = missing_version {
+            if !branded_page_content {
+              el.prepend(&version_notice(id_arxiv, version), ContentType::Html);
+            }
+          }
+          branded_page_content = true;
+          Ok(())
+        }),
+        element!("footer[class='ltx_page_footer']", |el| {
+          if !branded_footer {
+            el.before(
+              &footer_navigation(id_arxiv, status, prev, next),
+              ContentType::Html,
+            );
+            el.prepend(FOOTER_LINKS, ContentType::Html);
+          }
+          branded_footer = true;
+          Ok(())
+        }),
+        element!("head", |el| {
+          if !branded_head {
+            el.append(&head_assets(id_arxiv), ContentType::Html);
+          }
+          branded_head = true;
+          Ok(())
+        }),
+        element!("body", |el| {
+          if !branded_body {
+            el.append(ACTIVE_JS, ContentType::Html);
+          }
+          branded_body = true;
+          Ok(())
+        }),
+      ],
+      // latexml output is well-formed; never bail out on ambiguous markup.
+      strict: false,
+      ..Settings::new()
+    },
+    |chunk: &[u8]| output.extend_from_slice(chunk),
+  );
+  if is_placeholder {
+    rewriter.write(main_content.as_bytes())?;
+  } else {
+    // completely unrelated, drop all "invisible times" characters (U+2062) until
+    // latexml's grammar starts producing them more sparingly -- on the way in.
+    let mut rest = main_content;
+    while let Some(at) = rest.find(INVISIBLE_TIMES) {
+      rewriter.write(&rest.as_bytes()[..at])?;
+      rewriter.write(b" lspace='0px' rspace='0px'>")?;
+      rest = &rest[at + INVISIBLE_TIMES.len()..];
+    }
+    rewriter.write(rest.as_bytes())?;
+  }
+  rewriter.end()?;
+  Ok(output)
+}
+
+static INVISIBLE_TIMES: &str = ">\u{2062}";
+
+/// Adds an attribute right before an existing one (rather than last), as in
+/// `<a class="x" target="_blank" href="http...">`.
+fn insert_attribute_before(
+  el: &mut Element,
+  before: &str,
+  name: &str,
+  value: &str,
+) -> HandlerResult {
+  let attributes: Vec<(String, String)> = el
+    .attributes()
+    .iter()
+    .map(|attribute| (attribute.name(), attribute.value()))
+    .collect();
+  let Some(at) = attributes.iter().position(|(key, _)| key == before) else {
+    return Ok(());
+  };
+  for (key, _) in attributes[at..].iter() {
+    el.remove_attribute(key);
+  }
+  el.set_attribute(name, value)?;
+  for (key, value) in attributes[at..].iter() {
+    el.set_attribute(key, value)?;
+  }
+  Ok(())
 }
 
 
-pub fn log_to_html(conversion_report: &str, id_arxiv: &str) -> String {
+pub fn log_to_html(conversion_report: &ConversionLog, id_arxiv: &str) -> String {
   // Match the report page's theme to its article's (glowup or default).
   let (fonts_css_url, ar5iv_css_url) = document_css_urls(id_arxiv);
   String::from(
@@ -336,33 +566,20 @@ pub fn log_to_html(conversion_report: &str, id_arxiv: &str) -> String {
     <div id="S1.p1" class="ltx_para">
       <p class="ltx_p">
 "### + &conversion_report
-    .split('\n')
-    .map(|line| {
-      let line = line.replace('\t', "&emsp;");
-      if line.starts_with("Warning:") {
-        "</p><p class=\"ltx_p\"><span class=\"ltx_WARNING\">".to_string() + &line + "</span>"
-      } else if line.starts_with("Error:") {
-        "</p><p class=\"ltx_p\"><span class=\"ltx_ERROR\">".to_string() + &line + "</span>"
-      } else if line.starts_with("Info:") {
-        "</p><p class=\"ltx_p\"><span class=\"ltx_INFO\">".to_string() + &line + "</span>"
-      } else if line.starts_with("Fatal:") {
-        "</p><p class=\"ltx_p\"><span class=\"ltx_FATAL\">".to_string() + &line + "</span>"
-      } else if line.starts_with("Conversion complete:")
-        || line.starts_with("Post-processing complete:")
-      {
-        // provide a colored final status
-        if line.contains(" fatal") {
-          "</p><p class=\"ltx_p\"><span class=\"ltx_FATAL\">".to_string() + &line + "</span>"
-        } else if line.contains(" error") {
-          "</p><p class=\"ltx_p\"><span class=\"ltx_ERROR\">".to_string() + &line + "</span>"
-        } else if line.contains(" warning") {
-          "</p><p class=\"ltx_p\"><span class=\"ltx_WARNING\">".to_string() + &line + "</span>"
-        } else {
-          "</p><p class=\"ltx_p\"><span class=\"ltx_INFO\">".to_string() + &line + "</span>"
+    .lines
+    .iter()
+    .map(|line| match line {
+      ReportLine::Entry(i) => {
+        let entry = &conversion_report.entries[*i];
+        let mut html = highlighted(entry.severity, &entry.headline());
+        for detail in entry.details.iter() {
+          html.push_str("<br>\n&emsp;");
+          html.push_str(detail);
         }
-      } else {
-        line
+        html
       }
+      ReportLine::Tally(line, severity) => highlighted(*severity, line),
+      ReportLine::Text(line) => line.replace('\t', "&emsp;"),
     })
     .collect::<Vec<_>>()
     .join("<br>\n")
@@ -376,19 +593,118 @@ pub fn log_to_html(conversion_report: &str, id_arxiv: &str) -> String {
 </html>"###
 }
 
+/// A message or tally of the conversion report, highlighted by its severity.
+fn highlighted(severity: Severity, line: &str) -> String {
+  "</p><p class=\"ltx_p\"><span class=\"".to_string()
+    + severity.as_ltx_class()
+    + "\">"
+    + line
+    + "</span>"
+}
+
 #[cfg(test)]
 mod tests {
   use super::*;
+  use regex::{Captures, NoExpand};
+
+  static END_HEAD: LazyLock<Regex> = LazyLock::new(|| Regex::new("</head>").unwrap());
+  static END_BODY: LazyLock<Regex> = LazyLock::new(|| Regex::new("</body>").unwrap());
+  static START_PAGE_CONTENT: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new("<div class=\"ltx_page_content\">").unwrap());
+  static START_FOOTER: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new("<footer class=\"ltx_page_footer\">").unwrap());
+  static SRC_ATTR: LazyLock<Regex> = LazyLock::new(|| Regex::new(" src=\"([^\"]+)").unwrap());
+  static DATA_SVG_ATTR: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(" data=\"([^\"]+)[.]svg").unwrap());
+
+  /// The original multi-pass implementation of `dirty_branded_ar5iv_html`, one
+  /// full-document regex replacement per step: the reference the streaming
+  /// rewriter is checked against. It only ever rewrote `src` and
+  /// `data="*.svg"` references.
+  fn regex_branded_ar5iv_html(
+    mut main_content: String,
+    id_arxiv: &str,
+    status: LatexmlStatus,
+    prev: Option<String>,
+    next: Option<String>,
+    missing_version: Option<MissingVersion>,
+  ) -> String {
+    // ensure main_content is a string if undefined
+    if main_content.is_empty() {
+      main_content = DOC_NOT_FOUND_TEMPLATE.to_string();
+    } else {
+      // Global replacements:
+      // 1. ensure we have a lang attribute otherwise, English being most common in arXiv
+      main_content = main_content.replacen("<html>", "<html lang=\"en\">", 1)
+      // 2. completely unrelated, drop all "invisible times" characters (U+2062) until
+      //    latexml's grammar starts producing them more sparingly.
+        .replace(INVISIBLE_TIMES," lspace='0px' rspace='0px'>");
+
+      // Note: replacen would be faster, but we can't access the title content
+      // .replacen("<title>", &format!("<title>[{}] ",id_arxiv), 1);
+      main_content = TITLE_ELEMENT.replace(&main_content, |caps: &Captures| {
+        let description = og_description(&main_content);
+        // 1. also add the arxiv id to the title element
+        // 2. this is also the best place to insert vendor-specific meta tags
+        String::from("<title>[")+id_arxiv+"] "+&caps[1]+"</title>"+&title_meta(id_arxiv, &caps[1], &description)
+      }).to_string();
+    }
+
+    let main_content_src = SRC_ATTR.replace_all(&main_content, |caps: &Captures| {
+      // leave as-is data URL images and remote sources
+      if caps[1].starts_with("data:") || caps[1].starts_with("http") {
+        String::from(" src=\"") + &caps[1]
+      } else {
+        String::from(" src=\"/html/") + id_arxiv + "/assets/" + &caps[1]
+      }
+    });
+    main_content = DATA_SVG_ATTR
+      .replace_all(&main_content_src, |caps: &Captures| {
+        if caps[1].starts_with("data:") || caps[1].starts_with("http") {
+          String::from(" data=\"") + &caps[1] + ".svg"
+        } else {
+          String::from(" data=\"/html/") + id_arxiv + "/assets/" + &caps[1] + ".svg"
+        }
+      })
+      .to_string();
+    main_content = EXTERNAL_HREF.replace_all(&main_content," target=\"_blank\" href=\"http").to_string();
+
+    // if a specific arXiv version was requested but we serve another, say so upfront.
+    if let Some(version) = missing_version {
+      let page_content = String::from("<div class=\"ltx_page_content\">")
+        + &version_notice(id_arxiv, &version);
+      main_content = START_PAGE_CONTENT
+        .replace(&main_content, NoExpand(&page_content))
+        .to_string();
+    }
+
+    let ar5iv_footer = footer_navigation(id_arxiv, &status, prev.as_deref(), next.as_deref())
+      + "<footer class=\"ltx_page_footer\">"
+      + FOOTER_LINKS;
+    main_content = START_FOOTER
+      .replace(&main_content, NoExpand(&ar5iv_footer))
+      .to_string();
+    main_content = END_HEAD
+      .replace(&main_content, NoExpand(&(head_assets(id_arxiv) + "</head>")))
+      .to_string();
+    main_content = END_BODY
+      .replace(&main_content, NoExpand(&(ACTIVE_JS.to_string() + "</body>")))
+      .to_string();
+    main_content
+  }
 
   #[test]
   fn empty_content_renders_not_found_shell() {
     let html = dirty_branded_ar5iv_html(
       String::new(),
       "1234.56789",
+      &[],
       LatexmlStatus::Fatal,
       None,
       None,
-    );
+      None,
+    )
+    .unwrap();
     assert!(html.contains("ar5iv-footer"));
     assert!(html.contains("/log/1234.56789"));
   }
@@ -400,17 +716,261 @@ mod tests {
     let html = dirty_branded_ar5iv_html(
       input.to_string(),
       "1234.56789",
+      &[],
       LatexmlStatus::Ok,
       None,
       None,
-    );
+      None,
+    )
+    .unwrap();
     assert!(html.contains(r#"<meta property="og:title" content="An &quot;quoted&quot; title">"#));
   }
 
   fn branded(id: &str) -> String {
     let input = r#"<html><head><title>t</title></head>
 <body><footer class="ltx_page_footer"></footer></body></html>"#;
-    dirty_branded_ar5iv_html(input.to_string(), id, LatexmlStatus::Ok, None, None)
+    dirty_branded_ar5iv_html(
+      input.to_string(),
+      id,
+      &[],
+      LatexmlStatus::Ok,
+      None,
+      None,
+      None,
+    )
+    .unwrap()
+  }
+
+  #[test]
+  fn title_and_abstract_are_extracted() {
+    let html = r#"<html><head><title>On things</title></head><body>
+<div class="ltx_abstract"><p class="ltx_p">We study <math><mi>x</mi></math> and <em>y</em>.</p></div>
+</body></html>"#;
+    assert_eq!(title_text(html), Some("On things"));
+    assert_eq!(abstract_text(html).as_deref(), Some("We study  and y."));
+    assert_eq!(abstract_text("<html></html>"), None);
+  }
+
+  #[test]
+  fn missing_version_is_announced() {
+    let input = r#"<html><head><title>t</title></head>
+<body><div class="ltx_page_main"><div class="ltx_page_content"><article></article></div>
+<footer class="ltx_page_footer"></footer></div></body></html>"#;
+    let html = dirty_branded_ar5iv_html(
+      input.to_string(),
+      "2105.04404",
+      &[],
+      LatexmlStatus::Ok,
+      None,
+      None,
+      Some(MissingVersion {
+        requested: String::from("v3"),
+        served: Some(String::from("v2")),
+      }),
+    )
+    .unwrap();
+    assert!(html.contains("ar5iv-version-notice"));
+    assert!(html.contains("arXiv version v3 of this article"));
+    assert!(html.contains("Showing version v2, the most recent converted by ar5iv, instead"));
+    assert!(html.contains(r#"href="https://arxiv.org/abs/2105.04404v3""#));
+    // the served bundle's own links stay version-less
+    assert!(html.contains(r#"href="/log/2105.04404""#));
+
+    let exact = branded("2105.04404v3");
+    assert!(!exact.contains("ar5iv-version-notice"));
+  }
+
+  const LATEXML_DOC: &str = "<!DOCTYPE html><html>
+<head>
+<meta http-equiv=\"Content-Type\" content=\"text/html; charset=UTF-8\">
+<title>On \"things\" &amp; stuff</title>
+<link rel=\"stylesheet\" href=\"LaTeXML.css\" type=\"text/css\">
+</head>
+<body>
+<div class=\"ltx_page_main\">
+<div class=\"ltx_page_content\">
+<article class=\"ltx_document\">
+<div class=\"ltx_abstract\"><p class=\"ltx_p\">We study <math><mi>x</mi><mo>\u{2062}</mo><mi>y</mi></math> in depth.</p></div>
+<img src=\"x1.png\" id=\"S1.F1.g1\" class=\"ltx_graphics\" width=\"240\" height=\"120\" alt=\"\">
+<img src=\"data:image/png;base64,AAAA\" alt=\"\">
+<img src=\"https://example.org/remote.png\" alt=\"\">
+<object data=\"x2.svg\" type=\"image/svg+xml\"></object>
+<a href=\"https://arxiv.org/abs/1234.5678\" title=\"arXiv\" class=\"ltx_ref ltx_href\">link</a>
+<a href=\"#S1\" class=\"ltx_ref\">local</a>
+<math><mi>a</mi><mo>\u{2062}</mo><mi>b</mi></math>
+</article>
+</div>
+<footer class=\"ltx_page_footer\">
+<div class=\"ltx_page_logo\">Generated by <a href=\"http://dlmf.nist.gov/LaTeXML/\">LaTeXML</a></div>
+</footer>
+</div>
+</body>
+</html>
+";
+
+  #[test]
+  fn streaming_branding_matches_regex_branding() {
+    let cases = [
+      (LATEXML_DOC, LatexmlStatus::Ok, None, None, None),
+      (
+        LATEXML_DOC,
+        LatexmlStatus::Fatal,
+        Some("2105.04403"),
+        Some("2105.04405"),
+        Some("v2"),
+      ),
+      ("", LatexmlStatus::Fatal, None, None, None),
+      (
+        r#"<html><head><title>An "quoted" title</title></head>
+<body><footer class="ltx_page_footer"></footer></body></html>"#,
+        LatexmlStatus::Warning,
+        None,
+        None,
+        None,
+      ),
+    ];
+    for (input, status, prev, next, version) in cases {
+      let version = version.map(|requested| MissingVersion {
+        requested: requested.to_string(),
+        served: None,
+      });
+      assert_eq!(
+        dirty_branded_ar5iv_html(
+          input.to_string(),
+          "2105.04404",
+          &[],
+          status.clone(),
+          prev.map(str::to_string),
+          next.map(str::to_string),
+          version.clone(),
+        )
+        .unwrap(),
+        regex_branded_ar5iv_html(
+          input.to_string(),
+          "2105.04404",
+          status.clone(),
+          prev.map(str::to_string),
+          next.map(str::to_string),
+          version,
+        )
+      );
+    }
+  }
+
+  #[test]
+  fn streaming_branding_differs_from_regex_branding_where_meant() {
+    // the regex passes only ever rewrote `src` and `data="*.svg"`: every
+    // other reference into the bundle is the streaming rewriter's own
+    let cases = [
+      (
+        r#"<img src="x1.png" srcset="x1.png 1x, x1@2x.png 2x">"#,
+        r#"<img src="/html/2105.04404/assets/x1.png" srcset="/html/2105.04404/assets/x1.png 1x, /html/2105.04404/assets/x1@2x.png 2x">"#,
+        r#"<img src="/html/2105.04404/assets/x1.png" srcset="x1.png 1x, x1@2x.png 2x">"#,
+      ),
+      (
+        r#"<video poster="x2.png"></video>"#,
+        r#"<video poster="/html/2105.04404/assets/x2.png"></video>"#,
+        r#"<video poster="x2.png"></video>"#,
+      ),
+      (
+        r#"<span style="background:url(x3.png)">styled</span>"#,
+        r#"<span style="background:url(/html/2105.04404/assets/x3.png)">styled</span>"#,
+        r#"<span style="background:url(x3.png)">styled</span>"#,
+      ),
+      (
+        r#"<a href="supplement.pdf">supplement</a>"#,
+        r#"<a href="/html/2105.04404/assets/supplement.pdf">supplement</a>"#,
+        r#"<a href="supplement.pdf">supplement</a>"#,
+      ),
+    ];
+    for (fragment, streamed, regexed) in cases {
+      let input = String::from(
+        r#"<html><head><title>t</title></head>
+<body><div class="ltx_page_content">"#,
+      ) + fragment
+        + r#"</div><footer class="ltx_page_footer"></footer></body></html>"#;
+      let streaming = dirty_branded_ar5iv_html(
+        input.clone(),
+        "2105.04404",
+        &[],
+        LatexmlStatus::Ok,
+        None,
+        None,
+        None,
+      )
+      .unwrap();
+      let regex =
+        regex_branded_ar5iv_html(input, "2105.04404", LatexmlStatus::Ok, None, None, None);
+      assert!(streaming.contains(streamed), "{fragment}");
+      assert!(regex.contains(regexed), "{fragment}");
+      // ... and that is all the two disagree on
+      assert_eq!(streaming.replace(streamed, regexed), regex);
+    }
+  }
+
+  #[test]
+  fn streaming_branding_rewrites_references() {
+    let html = branded_with(LATEXML_DOC, "2105.04404");
+    assert!(html.starts_with("<!DOCTYPE html><html lang=\"en\">"));
+    assert!(html.contains("<title>[2105.04404] On \"things\" &amp; stuff</title>"));
+    assert!(html.contains(r#"<img src="/html/2105.04404/assets/x1.png" id="S1.F1.g1""#));
+    assert!(html.contains(r#"<img src="data:image/png;base64,AAAA""#));
+    assert!(html.contains(r#"<img src="https://example.org/remote.png""#));
+    assert!(html.contains(r#"<object data="/html/2105.04404/assets/x2.svg""#));
+    assert!(html.contains(
+      r#"<a target="_blank" href="https://arxiv.org/abs/1234.5678" title="arXiv" class="ltx_ref ltx_href">"#
+    ));
+    assert!(html.contains(r##"<a href="#S1" class="ltx_ref">"##));
+    assert!(!html.contains('\u{2062}'));
+    assert!(html.contains(r#"<meta property="og:description" content="We study  in depth.">"#));
+  }
+
+  #[test]
+  fn every_asset_reference_points_into_the_bundle() {
+    let input = r##"<html><head><title>t</title>
+<link rel="stylesheet" href="LaTeXML.css" type="text/css"></head>
+<body><div class="ltx_page_content">
+<a href="supplement.pdf" class="ltx_ref">supplement</a>
+<a href="#S1" class="ltx_ref">S1</a>
+<a href="mailto:someone@example.org">mail</a>
+<img src="x1.png" srcset="x1.png 1x, x1@2x.png 2x, data:image/png;base64,AA== 3x" alt="">
+<video poster="x2.png" controls><source src="x2.mp4" type="video/mp4"></video>
+<object data="x3.pdf" type="application/pdf"></object>
+<span style="background:url(x4.png) no-repeat;color:red">styled</span>
+<span style="background:url(https://example.org/x.png)">remote</span>
+<img src="/assets/ar5iv.png" alt="">
+</div><footer class="ltx_page_footer"></footer></body></html>"##;
+    let html = branded_with(input, "2105.04404");
+    for expected in [
+      // (`<link href>` is deliberately not pointed into the bundle: its
+      // LaTeXML.css is not to load next to ar5iv's stylesheets)
+      r#"<link rel="stylesheet" href="LaTeXML.css" type="text/css">"#,
+      r#"<a href="/html/2105.04404/assets/supplement.pdf" class="ltx_ref">"#,
+      r##"<a href="#S1" class="ltx_ref">"##,
+      r#"<a href="mailto:someone@example.org">"#,
+      r#"srcset="/html/2105.04404/assets/x1.png 1x, /html/2105.04404/assets/x1@2x.png 2x, data:image/png;base64,AA== 3x""#,
+      r#"<video poster="/html/2105.04404/assets/x2.png" controls>"#,
+      r#"<source src="/html/2105.04404/assets/x2.mp4" type="video/mp4">"#,
+      r#"<object data="/html/2105.04404/assets/x3.pdf" type="application/pdf">"#,
+      r#"style="background:url(/html/2105.04404/assets/x4.png) no-repeat;color:red""#,
+      r#"style="background:url(https://example.org/x.png)""#,
+      r#"<img src="/assets/ar5iv.png" alt="">"#,
+    ] {
+      assert!(html.contains(expected), "missing {expected}");
+    }
+  }
+
+  fn branded_with(input: &str, id: &str) -> String {
+    dirty_branded_ar5iv_html(
+      input.to_string(),
+      id,
+      &[],
+      LatexmlStatus::Ok,
+      None,
+      None,
+      None,
+    )
+    .unwrap()
   }
 
   #[test]
@@ -435,12 +995,31 @@ mod tests {
 
   #[test]
   fn conversion_report_matches_article_theme() {
-    let glowup = log_to_html("Status:conversion:0", "2606.01234");
+    let glowup = log_to_html(&ConversionLog::parse("Status:conversion:0"), "2606.01234");
     assert!(glowup.contains("ar5iv.0.9.0.css"));
     assert!(!glowup.contains("ar5iv.0.8.4.css"));
 
-    let default = log_to_html("Status:conversion:0", "2605.04404");
+    let default = log_to_html(&ConversionLog::parse("Status:conversion:0"), "2605.04404");
     assert!(default.contains("ar5iv.0.8.5.css"));
     assert!(!default.contains("0.9.0"));
   }
+
+  #[test]
+  fn conversion_report_renders_the_parsed_messages() {
+    let log = ConversionLog::parse(
+      "Error:undefined:\\foo The token T_CS[\\foo] is not defined.
+\tat paper.tex; line 12 col 15 - line 12 col 19
+Conversion complete: 1 error; 1 undefined macro[\\foo]
+Status:conversion:2",
+    );
+    let html = log_to_html(&log, "2605.04404");
+    assert!(html.contains(
+      r#"<span class="ltx_ERROR">Error:undefined:\foo The token T_CS[\foo] is not defined.</span><br>
+&emsp;at paper.tex; line 12 col 15 - line 12 col 19"#
+    ));
+    assert!(html.contains(
+      r#"<span class="ltx_ERROR">Conversion complete: 1 error; 1 undefined macro[\foo]</span>"#
+    ));
+    assert!(html.contains("<br>\nStatus:conversion:2"));
+  }
 }
@@ -0,0 +1,190 @@
+use rocket::request::{self, FromRequest, Request};
+use rocket::response::{self, Responder};
+use rocket::tokio::task::spawn_blocking;
+use std::io::{self, Read, Write};
+
+use crate::cache_backend::SharedBytes;
+
+/// Brotli quality of cached text: most of the ratio of the highest levels, at
+/// a fraction of their time.
+const BROTLI_QUALITY: u32 = 5;
+const BROTLI_WINDOW: u32 = 22;
+const GZIP_LEVEL: u32 = 6;
+
+/// The content codings cached pages and logs are stored in -- and served in,
+/// to clients accepting them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+  Brotli,
+  Gzip,
+}
+
+impl Encoding {
+  /// The name of the coding, in `Accept-Encoding` and `Content-Encoding`.
+  pub fn name(self) -> &'static str {
+    match self {
+      Encoding::Brotli => "br",
+      Encoding::Gzip => "gzip",
+    }
+  }
+
+  pub fn from_name(name: &str) -> Option<Self> {
+    match name {
+      "br" => Some(Encoding::Brotli),
+      "gzip" => Some(Encoding::Gzip),
+      _ => None,
+    }
+  }
+
+  pub fn compress(self, bytes: &[u8]) -> io::Result<Vec<u8>> {
+    match self {
+      Encoding::Brotli => {
+        let mut compressed = Vec::with_capacity(bytes.len() / 8);
+        let mut writer =
+          brotli::CompressorWriter::new(&mut compressed, 4096, BROTLI_QUALITY, BROTLI_WINDOW);
+        writer.write_all(bytes)?;
+        writer.flush()?;
+        drop(writer);
+        Ok(compressed)
+      }
+      Encoding::Gzip => {
+        let compressed = Vec::with_capacity(bytes.len() / 8);
+        let mut encoder =
+          flate2::write::GzEncoder::new(compressed, flate2::Compression::new(GZIP_LEVEL));
+        encoder.write_all(bytes)?;
+        encoder.finish()
+      }
+    }
+  }
+
+  pub fn decompress(self, bytes: &[u8]) -> io::Result<Vec<u8>> {
+    let mut decompressed = Vec::with_capacity(bytes.len() * 8);
+    match self {
+      Encoding::Brotli => brotli::Decompressor::new(bytes, 4096).read_to_end(&mut decompressed)?,
+      Encoding::Gzip => flate2::read::GzDecoder::new(bytes).read_to_end(&mut decompressed)?,
+    };
+    Ok(decompressed)
+  }
+}
+
+/// The codings a request accepts, by its `Accept-Encoding` header (none
+/// without one).
+#[derive(Debug, Default)]
+pub struct AcceptEncoding(Vec<(String, f32)>);
+
+impl AcceptEncoding {
+  pub fn parse(header: &str) -> Self {
+    AcceptEncoding(
+      header
+        .split(',')
+        .filter_map(|coding| {
+          let mut params = coding.split(';');
+          let name = params.next()?.trim().to_ascii_lowercase();
+          let quality = params
+            .filter_map(|param| param.trim().strip_prefix("q="))
+            .find_map(|q| q.trim().parse().ok())
+            .unwrap_or(1.0);
+          (!name.is_empty()).then_some((name, quality))
+        })
+        .collect(),
+    )
+  }
+
+  /// Whether a coding is acceptable: listed (or matched by `*`) with a
+  /// non-zero quality.
+  pub fn accepts(&self, encoding: Encoding) -> bool {
+    let quality = |name: &str| {
+      self
+        .0
+        .iter()
+        .find(|(coding, _)| coding == name)
+        .map(|(_, quality)| *quality)
+    };
+    quality(encoding.name())
+      .or_else(|| quality("*"))
+      .is_some_and(|quality| quality > 0.0)
+  }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AcceptEncoding {
+  type Error = ();
+  async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, ()> {
+    let header = req.headers().get("Accept-Encoding").collect::<Vec<_>>();
+    request::Outcome::Success(AcceptEncoding::parse(&header.join(",")))
+  }
+}
+
+/// A response body, possibly still in the coding it was cached in.
+#[derive(Debug, PartialEq)]
+pub struct EncodedBody {
+  pub body: SharedBytes,
+  pub encoding: Option<Encoding>,
+}
+
+impl EncodedBody {
+  pub fn identity(body: SharedBytes) -> Self {
+    EncodedBody {
+      body,
+      encoding: None,
+    }
+  }
+
+  /// The body as the client accepts it: as is, or decompressed (off the async
+  /// workers).
+  pub async fn for_client(self, accept: &AcceptEncoding) -> io::Result<EncodedBody> {
+    match self.encoding {
+      Some(encoding) if !accept.accepts(encoding) => {
+        let body = self.body;
+        let decompressed = spawn_blocking(move || encoding.decompress(&body))
+          .await
+          .map_err(io::Error::other)??;
+        Ok(EncodedBody::identity(SharedBytes::from(decompressed)))
+      }
+      _ => Ok(self),
+    }
+  }
+}
+
+impl<'r> Responder<'r, 'static> for EncodedBody {
+  fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+    let mut response = self.body.respond_to(req)?;
+    // caches in between must key on it, since the same URL has several codings
+    response.set_raw_header("Vary", "Accept-Encoding");
+    if let Some(encoding) = self.encoding {
+      response.set_raw_header("Content-Encoding", encoding.name());
+    }
+    Ok(response)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn cached_text_round_trips_through_both_codings() {
+    let html = "<math><mi>x</mi></math>".repeat(1000);
+    for encoding in [Encoding::Brotli, Encoding::Gzip] {
+      let compressed = encoding.compress(html.as_bytes()).unwrap();
+      assert!(compressed.len() * 10 < html.len(), "{encoding:?}");
+      assert_eq!(encoding.decompress(&compressed).unwrap(), html.as_bytes());
+      assert_eq!(Encoding::from_name(encoding.name()), Some(encoding));
+    }
+  }
+
+  #[test]
+  fn accepted_codings_respect_their_quality() {
+    let accept = AcceptEncoding::parse("gzip, deflate, br;q=0.8");
+    assert!(accept.accepts(Encoding::Brotli));
+    assert!(accept.accepts(Encoding::Gzip));
+    let accept = AcceptEncoding::parse("GZIP;q=0.5, br;q=0");
+    assert!(!accept.accepts(Encoding::Brotli));
+    assert!(accept.accepts(Encoding::Gzip));
+    let accept = AcceptEncoding::parse("*;q=1, gzip;q=0");
+    assert!(accept.accepts(Encoding::Brotli));
+    assert!(!accept.accepts(Encoding::Gzip));
+    assert!(!AcceptEncoding::default().accepts(Encoding::Gzip));
+    assert!(!AcceptEncoding::parse("identity").accepts(Encoding::Brotli));
+  }
+}
@@ -0,0 +1,121 @@
+use rocket::serde::{Deserialize, Serialize};
+use rocket::tokio::fs;
+use rocket::tokio::time::timeout;
+use rocket_db_pools::deadpool_redis::redis::cmd;
+use rocket_db_pools::deadpool_redis::Pool;
+use std::future::Future;
+use std::time::Duration;
+
+use crate::assemble_asset::build_paper_path;
+use crate::cache::split_arxiv_field;
+
+/// How long a check may take before it counts as failed: a hung mount or an
+/// unreachable Redis must not hang the load balancer's probe.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The `[health]` table of Rocket.toml: the paper whose bundle must be on the
+/// data mount for the service to be ready.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct HealthConfig {
+  pub sentinel: String,
+}
+
+impl Default for HealthConfig {
+  fn default() -> Self {
+    HealthConfig {
+      sentinel: String::from("1910.06709"),
+    }
+  }
+}
+
+/// The outcome of one readiness check. Checks that are not `required` (Redis,
+/// with another cache backend) are reported, but don't make the service
+/// unready.
+#[derive(Debug, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Check {
+  pub ok: bool,
+  pub required: bool,
+  pub detail: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Readiness {
+  pub ready: bool,
+  pub redis: Check,
+  pub data: Check,
+  pub paper_order: Check,
+}
+
+async fn checked<F>(required: bool, check: F) -> Check
+where
+  F: Future<Output = Result<String, String>>,
+{
+  let outcome = timeout(CHECK_TIMEOUT, check)
+    .await
+    .unwrap_or_else(|_| Err(format!("timed out after {CHECK_TIMEOUT:?}")));
+  match outcome {
+    Ok(detail) => Check {
+      ok: true,
+      required,
+      detail,
+    },
+    Err(detail) => Check {
+      ok: false,
+      required,
+      detail,
+    },
+  }
+}
+
+/// Checks that the Redis pool answers a `PING`, that the sentinel paper's
+/// bundle is on the data mount, and that `paper_order` is populated.
+pub async fn readiness(redis: Option<&Pool>, redis_required: bool, sentinel: &str) -> Readiness {
+  let redis_check = checked(redis_required, async {
+    let pool = redis.ok_or("no Redis pool")?;
+    let mut conn = pool.get().await.map_err(|e| e.to_string())?;
+    cmd("PING")
+      .query_async::<_, String>(&mut *conn)
+      .await
+      .map_err(|e| e.to_string())
+  });
+  let data_check = checked(true, async {
+    let (field_opt, id) = split_arxiv_field(sentinel);
+    let bundle = build_paper_path(field_opt, id)
+      .map_err(|_| format!("no bundle for the sentinel {sentinel}"))?;
+    let metadata = fs::metadata(&bundle.path)
+      .await
+      .map_err(|e| format!("{}: {e}", bundle.path.display()))?;
+    Ok(format!(
+      "{} ({} bytes)",
+      bundle.path.display(),
+      metadata.len()
+    ))
+  });
+  let paper_order_check = checked(redis_required, async {
+    let pool = redis.ok_or("no Redis pool")?;
+    let mut conn = pool.get().await.map_err(|e| e.to_string())?;
+    let papers = cmd("HLEN")
+      .arg("paper_order")
+      .query_async::<_, u64>(&mut *conn)
+      .await
+      .map_err(|e| e.to_string())?;
+    if papers > 0 {
+      Ok(format!("{papers} papers"))
+    } else {
+      Err(String::from("empty, run cache_adjacency_map"))
+    }
+  });
+  let (redis, data, paper_order) = rocket::tokio::join!(redis_check, data_check, paper_order_check);
+  let ready = [&redis, &data, &paper_order]
+    .iter()
+    .all(|check| check.ok || !check.required);
+  Readiness {
+    ready,
+    redis,
+    data,
+    paper_order,
+  }
+}
@@ -1,5 +1,21 @@
+pub mod access_log;
 pub mod assemble_asset;
+pub mod assemble_error;
+pub mod assembly_budget;
+pub mod asset_urls;
 pub mod cache;
+pub mod cache_backend;
+pub mod circuit_breaker;
+pub mod conditional;
 pub mod constants;
+pub mod conversion_log;
 pub mod dirty_templates;
+pub mod encoding;
+pub mod health;
+pub mod metadata;
+pub mod metrics;
 pub mod paper_order;
+pub mod single_flight;
+pub mod sniff;
+pub mod zip_index;
+pub mod zip_stream;
@@ -1,21 +1,38 @@
 #[macro_use]
 extern crate rocket;
+use rocket::fairing::AdHoc;
 use rocket::fs::NamedFile;
 use rocket::http::ContentType;
 use rocket::http::Header;
 use rocket::http::Status;
+use rocket::request::{self, FromParam, FromRequest};
 use rocket::response::{self, content, status, Redirect, Responder};
+use rocket::serde::json::{json, Json, Value};
+use rocket::serde::Deserialize;
 use rocket::{Request, State};
 use rocket_db_pools::Connection;
 use rocket_db_pools::Database;
 use rocket_dyn_templates::Template;
 
+use ar5iv::access_log::{AccessLog, RequestLog};
 use ar5iv::assemble_asset::fetch_zip;
+use ar5iv::assemble_error::AssembleError;
+use ar5iv::assembly_budget::AssemblyBudget;
 use ar5iv::cache::{
-  assemble_log_with_cache, assemble_paper_asset_with_cache, assemble_paper_with_cache, Cache,
-  LuckyStore,
+  assemble_log_report_shared, assemble_log_with_cache, assemble_paper_asset_with_cache,
+  assemble_paper_metadata_shared, assemble_paper_page_with_cache, assemble_paper_with_cache,
+  build_arxiv_id, inspect_cached, paper_fingerprint, prewarm_paper, purge_cached,
+  site_asset_with_cache, split_arxiv_field, AssetFallback, Cache, CacheConfig, LuckyStore,
 };
+use ar5iv::cache_backend::PaperCache;
+use ar5iv::conditional::Ranged;
 use ar5iv::constants::{AR5IV_CSS_URL, AR5IV_FONTS_CSS_URL, SITE_CSS_URL};
+use ar5iv::conversion_log::ConversionLog;
+use ar5iv::encoding::{AcceptEncoding, EncodedBody};
+use ar5iv::health::{readiness, HealthConfig, Readiness};
+use ar5iv::metadata::PaperMetadata;
+use ar5iv::metrics::{write_gauge, RequestMetrics, METRICS};
+use ar5iv::zip_index::ZIP_INDEXES;
 use regex::Regex;
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
@@ -76,15 +93,18 @@ fn is_plausible_arxiv_id(field_opt: Option<&str>, id: &str) -> bool {
 }
 
 /// Fallback responses for /html/ requests we cannot serve locally:
-/// plausible arXiv ids are forwarded to arxiv.org, the rest get a 404.
+/// plausible arXiv ids we hold no bundle for are forwarded to arxiv.org, the
+/// rest get a 404 -- and bundles we fail to assemble, the 500 page.
 #[derive(Responder)]
-// Neither variant is boxed: the two are nearly the same size (so no stack is
-// wasted on the smaller one), and the value is built once per fallback response
-// and immediately consumed -- boxing would only add a heap allocation.
+// Unlike `LogFallback`, nothing is boxed: the redirect and the 404 template are
+// nearly the same size, so only the small `Failed` leaves stack unused -- on a
+// value built once per fallback response and immediately consumed, where
+// boxing would only add a heap allocation.
 enum HtmlFallback {
   Redirect(Redirect),
   #[response(status = 404)]
   NotFound(Template),
+  Failed(AssembleError),
 }
 
 /// Cache-Control values: versioned site assets are immutable; paper pages and
@@ -141,17 +161,24 @@ async fn favicon() -> Option<CacheControlled<NamedFile>> {
 
 #[get("/html/<id>")]
 async fn get_html(
-  conn: Option<Connection<Cache>>,
+  cache: Option<PaperCache>,
+  accept: AcceptEncoding,
+  log: RequestLog,
   id: &str,
-) -> Result<CacheControlled<content::RawHtml<String>>, HtmlFallback> {
-  if let Some(paper) = assemble_paper_with_cache(conn, None, id).await {
-    Ok(CacheControlled(content::RawHtml(paper), CC_PAPER))
-  } else if is_plausible_arxiv_id(None, id) {
+) -> Result<CacheControlled<content::RawHtml<EncodedBody>>, HtmlFallback> {
+  match assemble_paper_with_cache(cache, None, id, &accept, &log).await {
+    Ok(paper) => return Ok(CacheControlled(content::RawHtml(paper), CC_PAPER)),
+    Err(AssembleError::NotFound) => {}
+    Err(e) => return Err(HtmlFallback::Failed(e)),
+  }
+  if is_plausible_arxiv_id(None, id) {
+    METRICS.inc("ar5iv_html_fallbacks_total", &[("outcome", "redirect")]);
     Err(HtmlFallback::Redirect(Redirect::temporary(format!(
       "https://arxiv.org/abs/{}",
       percent_encode_id(id)
     ))))
   } else {
+    METRICS.inc("ar5iv_html_fallbacks_total", &[("outcome", "not_found")]);
     let mut map = default_context();
     map.insert("id", id);
     Err(HtmlFallback::NotFound(Template::render("404", &map)))
@@ -159,19 +186,26 @@ async fn get_html(
 }
 #[get("/html/<field>/<id>", rank = 2)]
 async fn get_field_html(
-  conn: Option<Connection<Cache>>,
+  cache: Option<PaperCache>,
+  accept: AcceptEncoding,
+  log: RequestLog,
   field: &str,
   id: &str,
-) -> Result<CacheControlled<content::RawHtml<String>>, HtmlFallback> {
-  if let Some(paper) = assemble_paper_with_cache(conn, Some(field), id).await {
-    Ok(CacheControlled(content::RawHtml(paper), CC_PAPER))
-  } else if is_plausible_arxiv_id(Some(field), id) {
+) -> Result<CacheControlled<content::RawHtml<EncodedBody>>, HtmlFallback> {
+  match assemble_paper_with_cache(cache, Some(field), id, &accept, &log).await {
+    Ok(paper) => return Ok(CacheControlled(content::RawHtml(paper), CC_PAPER)),
+    Err(AssembleError::NotFound) => {}
+    Err(e) => return Err(HtmlFallback::Failed(e)),
+  }
+  if is_plausible_arxiv_id(Some(field), id) {
+    METRICS.inc("ar5iv_html_fallbacks_total", &[("outcome", "redirect")]);
     Err(HtmlFallback::Redirect(Redirect::temporary(format!(
       "https://arxiv.org/abs/{}/{}",
       percent_encode_id(field),
       percent_encode_id(id)
     ))))
   } else {
+    METRICS.inc("ar5iv_html_fallbacks_total", &[("outcome", "not_found")]);
     let mut map = default_context();
     let arxiv_id = format!("{field}/{id}");
     map.insert("id", &arxiv_id);
@@ -179,30 +213,86 @@ async fn get_field_html(
   }
 }
 
+/// A page of a multi-page conversion, e.g. `Ch1.html`: anything else in its
+/// place is a legacy `<field>/<id>` pair, and forwards to those routes.
+struct PageName<'r>(&'r str);
+impl<'r> FromParam<'r> for PageName<'r> {
+  type Error = &'r str;
+  fn from_param(param: &'r str) -> Result<Self, Self::Error> {
+    if param.len() > ".html".len() && param.ends_with(".html") {
+      Ok(PageName(param))
+    } else {
+      Err(param)
+    }
+  }
+}
+
+#[get("/html/<id>/<page>", rank = 1)]
+async fn get_html_page(
+  cache: Option<PaperCache>,
+  accept: AcceptEncoding,
+  log: RequestLog,
+  id: &str,
+  page: PageName<'_>,
+) -> Result<CacheControlled<content::RawHtml<EncodedBody>>, AssembleError> {
+  let page = assemble_paper_page_with_cache(cache, None, id, page.0, &accept, &log).await?;
+  Ok(CacheControlled(content::RawHtml(page), CC_PAPER))
+}
+#[get("/html/<field>/<id>/<page>", rank = 5)]
+async fn get_field_html_page(
+  cache: Option<PaperCache>,
+  accept: AcceptEncoding,
+  log: RequestLog,
+  field: &str,
+  id: &str,
+  page: PageName<'_>,
+) -> Result<CacheControlled<content::RawHtml<EncodedBody>>, AssembleError> {
+  let page = assemble_paper_page_with_cache(cache, Some(field), id, page.0, &accept, &log).await?;
+  Ok(CacheControlled(content::RawHtml(page), CC_PAPER))
+}
+
 #[get("/html/<id>/assets/<path..>", rank = 3)]
 async fn get_paper_asset(
-  conn: Option<Connection<Cache>>,
+  cache: Option<PaperCache>,
+  log: RequestLog,
   id: &str,
   path: PathBuf,
-) -> Result<CacheControlled<(ContentType, Vec<u8>)>, Option<NamedFile>> {
+) -> Result<CacheControlled<Ranged>, AssetFallback> {
   let filename = path.to_string_lossy();
-  assemble_paper_asset_with_cache(conn, None, id, &filename)
+  assemble_paper_asset_with_cache(cache, None, id, &filename, &log)
     .await
     .map(|asset| CacheControlled(asset, CC_PAPER_ASSET))
 }
 #[get("/html/<field>/<id>/assets/<path..>", rank = 4)]
 async fn get_field_paper_asset(
-  conn: Option<Connection<Cache>>,
+  cache: Option<PaperCache>,
+  log: RequestLog,
   field: &str,
   id: &str,
   path: PathBuf,
-) -> Result<CacheControlled<(ContentType, Vec<u8>)>, Option<NamedFile>> {
+) -> Result<CacheControlled<Ranged>, AssetFallback> {
   let filename = path.to_string_lossy();
-  assemble_paper_asset_with_cache(conn, Some(field), id, &filename)
+  assemble_paper_asset_with_cache(cache, Some(field), id, &filename, &log)
     .await
     .map(|asset| CacheControlled(asset, CC_PAPER_ASSET))
 }
 
+#[get("/api/paper/<id>")]
+async fn get_paper_metadata(
+  cache: Option<PaperCache>,
+  id: &str,
+) -> Result<Json<PaperMetadata>, AssembleError> {
+  assemble_paper_metadata_shared(cache, None, id).await.map(Json)
+}
+#[get("/api/paper/<field>/<id>")]
+async fn get_field_paper_metadata(
+  cache: Option<PaperCache>,
+  field: &str,
+  id: &str,
+) -> Result<Json<PaperMetadata>, AssembleError> {
+  assemble_paper_metadata_shared(cache, Some(field), id).await.map(Json)
+}
+
 #[get("/abs/<field>/<id>")]
 async fn abs_field(field: &str, id: &str) -> Redirect {
   let to_uri = String::from("/html/") + field + "/" + id;
@@ -239,17 +329,15 @@ async fn pdf(id: String) -> Redirect {
 }
 
 #[get("/assets/<name>")]
-async fn assets(name: &str) -> Option<CacheControlled<NamedFile>> {
-  NamedFile::open(Path::new("assets/").join(name))
+async fn assets(cache: Option<PaperCache>, name: &str) -> Option<CacheControlled<Ranged>> {
+  site_asset_with_cache(cache, &Path::new("assets/").join(name))
     .await
-    .ok()
     .map(|f| CacheControlled(f, CC_IMMUTABLE))
 }
 #[get("/assets/fonts/<name>")]
-async fn font_assets(name: &str) -> Option<CacheControlled<NamedFile>> {
-  NamedFile::open(Path::new("assets/fonts/").join(name))
+async fn font_assets(cache: Option<PaperCache>, name: &str) -> Option<CacheControlled<Ranged>> {
+  site_asset_with_cache(cache, &Path::new("assets/fonts/").join(name))
     .await
-    .ok()
     .map(|f| CacheControlled(f, CC_IMMUTABLE))
 }
 
@@ -261,43 +349,92 @@ fn general_not_found(req: &Request) -> Template {
   Template::render("404", &map)
 }
 
+/// API clients get a JSON error body rather than the HTML 404 page.
+#[catch(404)]
+fn api_not_found(req: &Request) -> Value {
+  json!({ "error": "not found", "path": req.uri().path().to_string() })
+}
+
+/// Conversion reports: the HTML page, or the parsed log for `<id>.json`.
+#[derive(Responder)]
+enum LogReport {
+  Html(content::RawHtml<EncodedBody>),
+  Json(Json<ConversionLog>),
+}
+
+/// Conversion reports we cannot serve: missing ones (on the 404 page), and
+/// those we failed to assemble.
+#[derive(Responder)]
+enum LogFallback {
+  NotFound(Box<Template>),
+  Failed(AssembleError),
+}
+
+async fn assemble_log_report_for(
+  cache: Option<PaperCache>,
+  accept: &AcceptEncoding,
+  log: &RequestLog,
+  field_opt: Option<&str>,
+  id: &str,
+) -> Result<LogReport, AssembleError> {
+  if let Some(id_core) = id.strip_suffix(".json") {
+    let report = assemble_log_report_shared(field_opt, id_core).await?;
+    Ok(LogReport::Json(Json(report)))
+  } else {
+    let html_log = assemble_log_with_cache(cache, field_opt, id, accept, log).await?;
+    Ok(LogReport::Html(content::RawHtml(html_log)))
+  }
+}
+
 #[get("/log/<id>")]
 async fn get_log(
-  conn: Option<Connection<Cache>>,
+  cache: Option<PaperCache>,
+  accept: AcceptEncoding,
+  log: RequestLog,
   id: &str,
-) -> Result<content::RawHtml<String>, Template> {
-  if let Some(paper) = assemble_log_with_cache(conn, None, id).await {
-    Ok(content::RawHtml(paper))
-  } else {
-    let mut map = default_context();
-    map.insert("id", id);
-    Err(Template::render("404", &map))
+) -> Result<LogReport, LogFallback> {
+  match assemble_log_report_for(cache, &accept, &log, None, id).await {
+    Ok(report) => Ok(report),
+    Err(AssembleError::NotFound) => {
+      let mut map = default_context();
+      map.insert("id", id);
+      Err(LogFallback::NotFound(Box::new(Template::render(
+        "404", &map,
+      ))))
+    }
+    Err(e) => Err(LogFallback::Failed(e)),
   }
 }
 #[get("/log/<field>/<id>")]
 async fn get_field_log(
-  conn: Option<Connection<Cache>>,
+  cache: Option<PaperCache>,
+  accept: AcceptEncoding,
+  log: RequestLog,
   field: &str,
   id: &str,
-) -> Result<content::RawHtml<String>, Template> {
-  if let Some(paper) = assemble_log_with_cache(conn, Some(field), id).await {
-    Ok(content::RawHtml(paper))
-  } else {
-    let mut map = default_context();
-    let arxiv_id = format!("{field}/{id}");
-    map.insert("id", &arxiv_id);
+) -> Result<LogReport, LogFallback> {
+  match assemble_log_report_for(cache, &accept, &log, Some(field), id).await {
+    Ok(report) => Ok(report),
+    Err(AssembleError::NotFound) => {
+      let mut map = default_context();
+      let arxiv_id = format!("{field}/{id}");
+      map.insert("id", &arxiv_id);
 
-    Err(Template::render("404", &map))
+      Err(LogFallback::NotFound(Box::new(Template::render(
+        "404", &map,
+      ))))
+    }
+    Err(e) => Err(LogFallback::Failed(e)),
   }
 }
 
 #[get("/source/<id>")]
-async fn get_source_zip(id: &str) -> Option<NamedFile> {
+async fn get_source_zip(id: &str) -> Option<Ranged> {
   let id_core: String = (*TRAILING_ZIP_EXT.replace(id, "")).to_owned();
   fetch_zip(None, &id_core).await
 }
 #[get("/source/<field>/<id>", rank = 2)]
-async fn get_field_source_zip(field: &str, id: &str) -> Option<NamedFile> {
+async fn get_field_source_zip(field: &str, id: &str) -> Option<Ranged> {
   let id_core: String = (*TRAILING_ZIP_EXT.replace(id, "")).to_owned();
   fetch_zip(Some(field), &id_core).await
 }
@@ -319,6 +456,261 @@ async fn feeling_lucky(
   }
 }
 
+/// The `[admin]` table of Rocket.toml. Without a token, the admin routes stay
+/// closed.
+#[derive(Default, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+struct AdminConfig {
+  token: String,
+}
+
+/// Guards the `/admin` routes: requests must carry `Authorization: Bearer
+/// <token>`, with the configured token.
+struct Admin;
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Admin {
+  type Error = ();
+  async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, ()> {
+    let token = req
+      .rocket()
+      .state::<AdminConfig>()
+      .map(|config| config.token.as_str())
+      .unwrap_or_default();
+    let given = req
+      .headers()
+      .get_one("Authorization")
+      .and_then(|value| value.strip_prefix("Bearer "));
+    match given {
+      Some(given) if !token.is_empty() && tokens_match(given, token) => {
+        request::Outcome::Success(Admin)
+      }
+      _ => request::Outcome::Error((Status::Unauthorized, ())),
+    }
+  }
+}
+
+/// Compares in constant time (for a given length), not to leak the token
+/// through response timings.
+fn tokens_match(given: &str, token: &str) -> bool {
+  given.len() == token.len()
+    && given
+      .bytes()
+      .zip(token.bytes())
+      .fold(0, |diff, (a, b)| diff | (a ^ b))
+      == 0
+}
+
+async fn admin_inspect_for(
+  cache: Option<PaperCache>,
+  field_opt: Option<&str>,
+  id: &str,
+) -> Result<Value, Status> {
+  let cache = cache.ok_or(Status::ServiceUnavailable)?;
+  let id_arxiv = build_arxiv_id(&field_opt, id);
+  let entries = inspect_cached(&cache, &id_arxiv)
+    .await
+    .map_err(|_| Status::ServiceUnavailable)?;
+  let bytes: u64 = entries.iter().map(|entry| entry.bytes).sum();
+  Ok(json!({
+    "id": id_arxiv,
+    "bundle_fingerprint": paper_fingerprint(field_opt, id),
+    "bytes": bytes,
+    "entries": entries,
+  }))
+}
+
+async fn admin_purge_for(
+  cache: Option<PaperCache>,
+  field_opt: Option<&str>,
+  id: &str,
+) -> Result<Value, Status> {
+  let cache = cache.ok_or(Status::ServiceUnavailable)?;
+  let id_arxiv = build_arxiv_id(&field_opt, id);
+  let purged = purge_cached(&cache, &id_arxiv)
+    .await
+    .map_err(|_| Status::ServiceUnavailable)?;
+  Ok(json!({ "id": id_arxiv, "purged": purged }))
+}
+
+/// The hit and miss counts and usage of the in-process hot tier, and how much
+/// of the assembly budget the cache misses in flight take.
+#[get("/cache")]
+async fn admin_cache_stats(_admin: Admin, cache: Option<PaperCache>) -> Value {
+  let budget = AssemblyBudget::get();
+  json!({
+    "hot_tier": cache.as_ref().and_then(|cache| cache.hot_tier_stats()),
+    "breaker": cache.map(|cache| cache.breaker_stats()),
+    "assembly": {
+      "budget_bytes": budget.budget_bytes(),
+      "reserved_bytes": budget.reserved_bytes(),
+    },
+  })
+}
+
+/// What is cached for an id (and its versions): each key, its size, time to
+/// live and bundle fingerprint.
+#[get("/cache/<id>")]
+async fn admin_inspect(
+  _admin: Admin,
+  cache: Option<PaperCache>,
+  id: &str,
+) -> Result<Value, Status> {
+  admin_inspect_for(cache, None, id).await
+}
+#[get("/cache/<field>/<id>")]
+async fn admin_inspect_field(
+  _admin: Admin,
+  cache: Option<PaperCache>,
+  field: &str,
+  id: &str,
+) -> Result<Value, Status> {
+  admin_inspect_for(cache, Some(field), id).await
+}
+
+/// Purges the cached paper, pages, log and assets of an id (and its versions).
+#[delete("/cache/<id>")]
+async fn admin_purge(_admin: Admin, cache: Option<PaperCache>, id: &str) -> Result<Value, Status> {
+  admin_purge_for(cache, None, id).await
+}
+#[delete("/cache/<field>/<id>")]
+async fn admin_purge_field(
+  _admin: Admin,
+  cache: Option<PaperCache>,
+  field: &str,
+  id: &str,
+) -> Result<Value, Status> {
+  admin_purge_for(cache, Some(field), id).await
+}
+
+/// Re-assembles (and so re-caches) the papers of a JSON list of ids, such as
+/// `["2105.04404", "math/0211159"]`: those we hold no bundle of are reported
+/// `missing`, those we failed to assemble `failed`, with why.
+#[post("/prewarm", data = "<ids>")]
+async fn admin_prewarm(_admin: Admin, cache: Option<PaperCache>, ids: Json<Vec<String>>) -> Value {
+  let mut warmed = Vec::new();
+  let mut missing = Vec::new();
+  let mut failed = Vec::new();
+  for id_arxiv in ids.into_inner() {
+    let (field_opt, id) = split_arxiv_field(&id_arxiv);
+    match prewarm_paper(cache.clone(), field_opt, id).await {
+      Ok(_) => warmed.push(id_arxiv),
+      Err(AssembleError::NotFound) => missing.push(id_arxiv),
+      Err(e) => failed.push(json!({
+        "id": id_arxiv,
+        "kind": e.kind(),
+        "error": e.to_string(),
+      })),
+    }
+  }
+  json!({ "warmed": warmed, "missing": missing, "failed": failed })
+}
+
+/// Liveness: the process answers.
+#[get("/healthz")]
+fn healthz() -> Value {
+  json!({ "status": "ok" })
+}
+
+/// Readiness: Redis answers, the data mount holds the sentinel paper, and the
+/// `paper_order` navigation is populated -- each check reported, with a 503
+/// unless all required ones pass.
+#[get("/readyz")]
+async fn readyz(
+  redis: Option<&State<Cache>>,
+  health: &State<HealthConfig>,
+) -> (Status, Json<Readiness>) {
+  let redis_required = CacheConfig::get().backend == "redis";
+  let readiness = readiness(
+    redis.map(|redis| &***redis),
+    redis_required,
+    &health.sentinel,
+  )
+  .await;
+  let status = if readiness.ready {
+    Status::Ok
+  } else {
+    Status::ServiceUnavailable
+  };
+  (status, Json(readiness))
+}
+
+/// Prometheus metrics: those recorded as requests go, and gauges of the Redis
+/// pool, the hot tier and the assembly budget.
+#[get("/metrics")]
+fn metrics(redis: Option<&State<Cache>>, cache: Option<PaperCache>) -> (ContentType, String) {
+  let mut out = METRICS.render();
+  if let Some(redis) = redis {
+    let status = redis.status();
+    write_gauge(
+      &mut out,
+      "ar5iv_redis_pool_connections",
+      "Connections of the Redis pool, by state.",
+      &[
+        ("state=\"max\"", status.max_size as f64),
+        ("state=\"open\"", status.size as f64),
+        ("state=\"available\"", status.available as f64),
+      ],
+    );
+    write_gauge(
+      &mut out,
+      "ar5iv_redis_pool_waiting",
+      "Requests waiting for a Redis connection.",
+      &[("", status.waiting as f64)],
+    );
+  }
+  if let Some(breaker) = cache.as_ref().map(|cache| cache.breaker_stats()) {
+    write_gauge(
+      &mut out,
+      "ar5iv_cache_breaker_open",
+      "Whether the circuit breaker of the cache is open, skipping the cache.",
+      &[("", if breaker.state == "open" { 1.0 } else { 0.0 })],
+    );
+    write_gauge(
+      &mut out,
+      "ar5iv_cache_breaker_trips",
+      "Times the circuit breaker of the cache opened since launch.",
+      &[("", breaker.trips as f64)],
+    );
+  }
+  if let Some(stats) = cache.and_then(|cache| cache.hot_tier_stats()) {
+    write_gauge(
+      &mut out,
+      "ar5iv_hot_tier_lookups",
+      "Lookups of the in-process hot tier since launch, by result.",
+      &[
+        ("result=\"hit\"", stats.hits as f64),
+        ("result=\"miss\"", stats.misses as f64),
+      ],
+    );
+    write_gauge(
+      &mut out,
+      "ar5iv_hot_tier_bytes",
+      "Bytes held by the hot tier, and its budget.",
+      &[
+        ("kind=\"used\"", stats.bytes as f64),
+        ("kind=\"budget\"", stats.budget as f64),
+      ],
+    );
+  }
+  let budget = AssemblyBudget::get();
+  write_gauge(
+    &mut out,
+    "ar5iv_assembly_budget_bytes",
+    "Memory of the assembly budget, and the part reserved by cache misses in flight.",
+    &[
+      ("kind=\"budget\"", budget.budget_bytes() as f64),
+      ("kind=\"reserved\"", budget.reserved_bytes() as f64),
+    ],
+  );
+  write_gauge(
+    &mut out,
+    "ar5iv_zip_indexes",
+    "Bundles whose ZIP index is cached.",
+    &[("", ZIP_INDEXES.len() as f64)],
+  );
+  (ContentType::Plain, out)
+}
+
 #[get("/robots.txt")]
 fn robots_txt() -> (ContentType, &'static str) {
   (
@@ -340,6 +732,10 @@ fn rocket() -> _ {
   rocket::build()
     .attach(Template::fairing())
     .attach(Cache::init())
+    .attach(CacheConfig::fairing())
+    .attach(AssemblyBudget::fairing())
+    .attach(RequestMetrics)
+    .attach(AccessLog::fairing())
     .mount(
       "/",
       routes![
@@ -351,27 +747,60 @@ fn rocket() -> _ {
         vanity_style_field,
         get_html,
         get_field_html,
+        get_html_page,
+        get_field_html_page,
         get_log,
         get_field_log,
         get_source_zip,
         get_field_source_zip,
         get_paper_asset,
         get_field_paper_asset,
+        get_paper_metadata,
+        get_field_paper_metadata,
         about,
         assets,
         font_assets,
         favicon,
         feeling_lucky,
+        metrics,
+        healthz,
+        readyz,
         robots_txt
       ],
     )
+    .mount(
+      "/admin",
+      routes![
+        admin_cache_stats,
+        admin_inspect,
+        admin_inspect_field,
+        admin_purge,
+        admin_purge_field,
+        admin_prewarm
+      ],
+    )
+    .attach(AdHoc::on_ignite("Admin config", |rocket| async {
+      let config = rocket
+        .figment()
+        .extract_inner::<AdminConfig>("admin")
+        .unwrap_or_default();
+      rocket.manage(config)
+    }))
+    .attach(AdHoc::on_ignite("Health config", |rocket| async {
+      let config = rocket
+        .figment()
+        .extract_inner::<HealthConfig>("health")
+        .unwrap_or_default();
+      rocket.manage(config)
+    }))
     .manage(LuckyStore::new())
     .register("/", catchers![general_not_found, default_catcher])
+    .register("/api", catchers![api_not_found])
 }
 
 #[cfg(test)]
 mod tests {
-  use rocket::http::Status;
+  use rocket::http::{Header, Status};
   use rocket::local::blocking::Client;
 
   fn client() -> Client {
@@ -436,6 +865,31 @@ mod tests {
     );
   }
 
+  #[test]
+  fn missing_papers_are_logged_without_a_bundle() {
+    let path = std::env::temp_dir().join(format!(
+      "ar5iv_access_log_test_{}.jsonl",
+      std::process::id()
+    ));
+    let figment = rocket::Config::figment().merge(("access_log.path", path.display().to_string()));
+    let client =
+      Client::tracked(super::rocket().configure(figment)).expect("valid rocket instance");
+    client.get("/html/2512.99999").dispatch();
+    client.get("/html/9999.99999").dispatch();
+    let log = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let records: Vec<rocket::serde::json::Value> = log
+      .lines()
+      .map(|line| rocket::serde::json::from_str(line).unwrap())
+      .collect();
+    assert_eq!(records.len(), 2);
+    for (record, outcome) in records.iter().zip(["redirected_to_arxiv", "not_found"]) {
+      assert!(record["arxiv_id"].is_string());
+      assert!(record["bundle"].is_null());
+      assert_eq!(record["outcome"], outcome);
+    }
+  }
+
   #[test]
   fn arxiv_id_scheme_validation() {
     use super::is_plausible_arxiv_id;
@@ -469,6 +923,59 @@ mod tests {
     );
   }
 
+  #[test]
+  fn unknown_non_image_asset_is_a_404() {
+    let client = client();
+    for uri in [
+      "/html/9999.99999/assets/style.css",
+      "/html/9999.99999/assets/script.js",
+      "/html/9999.99999/assets/x1",
+    ] {
+      let response = client.get(uri).dispatch();
+      assert_eq!(response.status(), Status::NotFound, "expected 404 for {uri}");
+    }
+  }
+
+  #[test]
+  fn pages_of_unknown_papers_are_a_404() {
+    let client = client();
+    for uri in ["/html/9999.99999/Ch1.html", "/html/math/0213159/Ch1.html"] {
+      let response = client.get(uri).dispatch();
+      assert_eq!(response.status(), Status::NotFound, "expected 404 for {uri}");
+    }
+    // legacy ids still reach the paper routes, rather than the page ones
+    let response = client.get("/html/math/0211159").dispatch();
+    assert_eq!(response.status(), Status::TemporaryRedirect);
+  }
+
+  #[test]
+  fn admin_routes_require_the_token() {
+    let client = client();
+    // no token is configured, so none is right
+    let response = client.get("/admin/cache/2105.04404").dispatch();
+    assert_eq!(response.status(), Status::Unauthorized);
+    let response = client
+      .delete("/admin/cache/2105.04404")
+      .header(Header::new("Authorization", "Bearer "))
+      .dispatch();
+    assert_eq!(response.status(), Status::Unauthorized);
+    assert!(super::tokens_match("s3cret", "s3cret"));
+    assert!(!super::tokens_match("s3cres", "s3cret"));
+    assert!(!super::tokens_match("s3cre", "s3cret"));
+  }
+
+  #[test]
+  fn unknown_paper_metadata_is_a_json_404() {
+    let client = client();
+    let response = client.get("/api/paper/9999.99999").dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+    assert_eq!(
+      response.headers().get_one("Content-Type"),
+      Some("application/json")
+    );
+    assert!(response.into_string().unwrap().contains("\"error\""));
+  }
+
   #[test]
   fn site_assets_are_served_immutable() {
     let client = client();
@@ -480,6 +987,22 @@ mod tests {
     );
   }
 
+  #[test]
+  fn site_assets_answer_conditional_requests() {
+    let client = client();
+    let response = client.get("/assets/ar5iv.0.8.5.css").dispatch();
+    let etag = response.headers().get_one("ETag").unwrap().to_string();
+    let body = response.into_bytes().unwrap();
+    // (served from the hot tier, the second time)
+    let again = client.get("/assets/ar5iv.0.8.5.css").dispatch();
+    assert_eq!(again.into_bytes().unwrap(), body);
+    let response = client
+      .get("/assets/ar5iv.0.8.5.css")
+      .header(Header::new("If-None-Match", etag))
+      .dispatch();
+    assert_eq!(response.status(), Status::NotModified);
+  }
+
   #[test]
   fn glowup_assets_are_served() {
     // the glowup theme files referenced by GLOWUP_ID_PREFIXES articles must
@@ -510,4 +1033,29 @@ mod tests {
     assert_eq!(response.status(), Status::Ok);
     assert!(response.into_string().unwrap().contains("Disallow: /log/"));
   }
+
+  #[test]
+  fn metrics_count_requests_by_route() {
+    let client = client();
+    client.get("/robots.txt").dispatch();
+    let response = client.get("/metrics").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let text = response.into_string().unwrap();
+    assert!(text.contains("ar5iv_http_requests_total{route=\"robots_txt\",status=\"200\"}"));
+    assert!(text.contains("# TYPE ar5iv_assembly_budget_bytes gauge"));
+  }
+
+  #[test]
+  fn health_and_readiness_are_reported() {
+    let client = client();
+    let response = client.get("/healthz").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    // (whether ready depends on the machine: each check is reported either way)
+    let response = client.get("/readyz").dispatch();
+    assert!([Status::Ok, Status::ServiceUnavailable].contains(&response.status()));
+    let readiness: rocket::serde::json::Value = response.into_json().unwrap();
+    for check in ["redis", "data", "paper_order"] {
+      assert!(readiness[check]["ok"].is_boolean(), "{check} is reported");
+    }
+  }
 }
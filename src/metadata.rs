@@ -0,0 +1,149 @@
+use rocket::serde::Serialize;
+use rocket::tokio::task::spawn_blocking;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use zip::ZipArchive;
+
+use crate::assemble_asset::{
+  adjacent_papers, build_paper_path, bundle_pages, log_to_status, BundleKind, LatexmlStatus,
+  PaperBundle,
+};
+use crate::assemble_error::AssembleError;
+use crate::cache::{build_arxiv_id, split_arxiv_version};
+use crate::cache_backend::PaperCache;
+use crate::constants::LOG_FILENAME;
+use crate::dirty_templates::{abstract_text, title_text};
+
+/// What we know about an article, as served by the `/api/paper/` routes.
+#[derive(Debug, Clone, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct PaperMetadata {
+  /// the arXiv id of the bundle being described
+  pub id: String,
+  /// the requested arXiv version, when we don't hold it (see `PaperBundle`)
+  pub missing_version: Option<String>,
+  pub title: Option<String>,
+  #[serde(rename = "abstract")]
+  pub abstract_text: Option<String>,
+  pub status: LatexmlStatus,
+  pub prev: Option<String>,
+  pub next: Option<String>,
+  /// the HTML pages of the bundle, its main page (described here) first
+  pub pages: Vec<String>,
+  /// the ZIP entries served under `/html/<id>/assets/`
+  pub assets: Vec<String>,
+  pub bundle: BundleKind,
+}
+
+/// The main document and log of a bundle, plus the names of all its pages
+/// and assets.
+struct MetadataParts {
+  html: String,
+  pages: Vec<String>,
+  log: String,
+  assets: Vec<String>,
+}
+
+pub async fn assemble_paper_metadata(
+  cache_opt: Option<PaperCache>,
+  field_opt: Option<&str>,
+  id: &str,
+) -> Result<PaperMetadata, AssembleError> {
+  let PaperBundle {
+    path: paper_path,
+    kind,
+    id: id_bundle,
+    missing_version,
+  } = build_paper_path(field_opt, id)?;
+  // Scan the ZIP like `assemble_paper` does, but only read the main document
+  // and the log -- assets are merely listed.
+  let parts = spawn_blocking(move || -> Result<MetadataParts, AssembleError> {
+    let zipf = File::open(paper_path)?;
+    let reader = BufReader::new(zipf);
+    let mut zip = ZipArchive::new(reader)?;
+    let pages = bundle_pages(&mut zip);
+    let mut html = String::new();
+    let mut log = String::new();
+    let mut assets = Vec::new();
+    for i in 0..zip.len() {
+      if let Ok(mut file) = zip.by_index(i) {
+        if file.is_file() {
+          match file.name() {
+            name if name.ends_with(".html") => {
+              if let Some(main_page) = pages.first().filter(|main_page| *main_page == name) {
+                html = AssembleError::read_text(&mut file, main_page)?;
+              }
+            }
+            name if name == LOG_FILENAME => {
+              if file.read_to_string(&mut log).is_err() {
+                log.clear();
+              }
+            }
+            other => assets.push(other.to_string()),
+          }
+        }
+      }
+    }
+    Ok(MetadataParts {
+      html,
+      pages,
+      log,
+      assets,
+    })
+  })
+  .await??;
+  let MetadataParts {
+    html,
+    pages,
+    log,
+    assets,
+  } = parts;
+  let status = if log.is_empty() {
+    LatexmlStatus::Fatal
+  } else {
+    log_to_status(&log)
+  };
+  let id_unversioned = build_arxiv_id(&field_opt, split_arxiv_version(id).0);
+  let (prev, next) = adjacent_papers(cache_opt.as_ref(), &id_unversioned).await;
+  Ok(PaperMetadata {
+    id: build_arxiv_id(&field_opt, &id_bundle),
+    missing_version,
+    title: title_text(&html).map(str::to_string),
+    abstract_text: abstract_text(&html),
+    status,
+    prev,
+    next,
+    pages,
+    assets,
+    bundle: kind,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use rocket::serde::json;
+
+  #[test]
+  fn metadata_serializes_with_lowercase_enums() {
+    let metadata = PaperMetadata {
+      id: String::from("2606.01234"),
+      missing_version: None,
+      title: Some(String::from("A title")),
+      abstract_text: Some(String::from("An abstract.")),
+      status: LatexmlStatus::Warning,
+      prev: None,
+      next: Some(String::from("2606.01235")),
+      pages: vec![String::from("index.html"), String::from("Ch1.html")],
+      assets: vec![String::from("x1.png")],
+      bundle: BundleKind::Oxidized,
+    };
+    let value: json::Value = json::from_str(&json::to_string(&metadata).unwrap()).unwrap();
+    assert_eq!(value["status"], "warning");
+    assert_eq!(value["bundle"], "oxidized");
+    assert_eq!(value["abstract"], "An abstract.");
+    assert_eq!(value["next"], "2606.01235");
+    assert_eq!(value["pages"][0], "index.html");
+    assert!(value["prev"].is_null());
+  }
+}
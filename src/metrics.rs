@@ -0,0 +1,312 @@
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::{Data, Request, Response};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+/// Seconds, from a request down to a stage of paper assembly.
+const SECONDS: &[f64] = &[
+  0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+/// Bytes of assembled papers: from a short note to a book.
+const BYTES: &[f64] = &[
+  16_384.0,
+  65_536.0,
+  262_144.0,
+  1_048_576.0,
+  4_194_304.0,
+  16_777_216.0,
+  67_108_864.0,
+];
+
+/// The metric families we export: name, help and, for histograms, buckets.
+const FAMILIES: &[(&str, &str, Option<&[f64]>)] = &[
+  (
+    "ar5iv_http_requests_total",
+    "Requests answered, by route and status.",
+    None,
+  ),
+  (
+    "ar5iv_http_request_duration_seconds",
+    "Time to answer a request, by route.",
+    Some(SECONDS),
+  ),
+  (
+    "ar5iv_cache_requests_total",
+    "Cache lookups, by keyspace (p: papers and pages, a: assets, l: logs) and result.",
+    None,
+  ),
+  (
+    "ar5iv_assembly_duration_seconds",
+    "Time spent assembling a paper on a cache miss, by stage (decompress, brand).",
+    Some(SECONDS),
+  ),
+  (
+    "ar5iv_assembled_paper_bytes",
+    "Size of the branded papers assembled on cache misses.",
+    Some(BYTES),
+  ),
+  (
+    "ar5iv_skipped_assets_total",
+    "Paper assets not buffered for exceeding a cap (ten_mib: caching, sixty_four_mib: buffering).",
+    None,
+  ),
+  (
+    "ar5iv_assembly_errors_total",
+    "Papers, pages, logs and assets we failed to assemble, by kind (corrupt, io, not_utf8, branding, panicked, overloaded).",
+    None,
+  ),
+  (
+    "ar5iv_zip_index_lookups_total",
+    "Bundles opened to read an entry, by whether their index was cached (hit) or read anew (miss).",
+    None,
+  ),
+  (
+    "ar5iv_html_fallbacks_total",
+    "Papers we could not serve, by outcome (redirect to arxiv.org, or not_found).",
+    None,
+  ),
+];
+
+type Labels = Vec<(&'static str, String)>;
+
+enum Series {
+  Counter(u64),
+  Histogram {
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+  },
+}
+
+/// The metrics of this process, in the Prometheus text format: counters and
+/// histograms recorded as requests go, to which `/metrics` adds the gauges it
+/// reads at scrape time.
+pub struct Metrics {
+  series: Mutex<BTreeMap<&'static str, BTreeMap<Labels, Series>>>,
+}
+
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+impl Metrics {
+  pub fn new() -> Self {
+    Metrics {
+      series: Mutex::new(BTreeMap::new()),
+    }
+  }
+
+  fn family(name: &str) -> Option<&'static (&'static str, &'static str, Option<&'static [f64]>)> {
+    FAMILIES.iter().find(|(family, _, _)| *family == name)
+  }
+
+  fn record(&self, name: &str, labels: &[(&'static str, &str)], value: f64) {
+    let Some(&(name, _, buckets)) = Metrics::family(name) else {
+      debug_assert!(false, "unknown metric {name}");
+      return;
+    };
+    let labels = labels
+      .iter()
+      .map(|(label, value)| (*label, value.to_string()))
+      .collect();
+    let Ok(mut series) = self.series.lock() else {
+      return;
+    };
+    let entry = series
+      .entry(name)
+      .or_default()
+      .entry(labels)
+      .or_insert_with(|| match buckets {
+        Some(bounds) => Series::Histogram {
+          buckets: vec![0; bounds.len()],
+          sum: 0.0,
+          count: 0,
+        },
+        None => Series::Counter(0),
+      });
+    match entry {
+      Series::Counter(count) => *count += value as u64,
+      Series::Histogram {
+        buckets: counts,
+        sum,
+        count,
+      } => {
+        // (only the first bucket fitting is counted: they add up when rendered)
+        if let Some(bucket) = buckets.and_then(|bounds| bounds.iter().position(|le| value <= *le)) {
+          counts[bucket] += 1;
+        }
+        *sum += value;
+        *count += 1;
+      }
+    }
+  }
+
+  pub fn inc(&self, name: &str, labels: &[(&'static str, &str)]) {
+    self.record(name, labels, 1.0);
+  }
+
+  pub fn observe(&self, name: &str, labels: &[(&'static str, &str)], value: f64) {
+    self.record(name, labels, value);
+  }
+
+  pub fn observe_duration(&self, name: &str, labels: &[(&'static str, &str)], elapsed: Duration) {
+    self.record(name, labels, elapsed.as_secs_f64());
+  }
+
+  /// A cache lookup of `key`, in its keyspace.
+  pub fn cache_lookup(&self, key: &str, hit: bool) {
+    let keyspace = key.split_once(':').map_or("", |(keyspace, _)| keyspace);
+    let result = if hit { "hit" } else { "miss" };
+    self.inc(
+      "ar5iv_cache_requests_total",
+      &[("keyspace", keyspace), ("result", result)],
+    );
+  }
+
+  /// The recorded metrics, in the text exposition format.
+  pub fn render(&self) -> String {
+    let mut out = String::new();
+    let Ok(series) = self.series.lock() else {
+      return out;
+    };
+    for (name, help, buckets) in FAMILIES {
+      let kind = if buckets.is_some() {
+        "histogram"
+      } else {
+        "counter"
+      };
+      writeln!(out, "# HELP {name} {help}\n# TYPE {name} {kind}").ok();
+      for (labels, value) in series.get(name).into_iter().flatten() {
+        match value {
+          Series::Counter(count) => {
+            writeln!(out, "{name}{} {count}", format_labels(labels, None)).ok();
+          }
+          Series::Histogram {
+            buckets: counts,
+            sum,
+            count,
+          } => {
+            let mut cumulative = 0;
+            for (le, bucket) in buckets.unwrap_or_default().iter().zip(counts) {
+              cumulative += bucket;
+              let labels = format_labels(labels, Some(&le.to_string()));
+              writeln!(out, "{name}_bucket{labels} {cumulative}").ok();
+            }
+            let labels_inf = format_labels(labels, Some("+Inf"));
+            let labels = format_labels(labels, None);
+            writeln!(out, "{name}_bucket{labels_inf} {count}").ok();
+            writeln!(out, "{name}_sum{labels} {sum}").ok();
+            writeln!(out, "{name}_count{labels} {count}").ok();
+          }
+        }
+      }
+    }
+    out
+  }
+}
+
+impl Default for Metrics {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+/// Appends a gauge read at scrape time, with its header.
+pub fn write_gauge(out: &mut String, name: &str, help: &str, samples: &[(&str, f64)]) {
+  writeln!(out, "# HELP {name} {help}\n# TYPE {name} gauge").ok();
+  for (labels, value) in samples {
+    if labels.is_empty() {
+      writeln!(out, "{name} {value}").ok();
+    } else {
+      writeln!(out, "{name}{{{labels}}} {value}").ok();
+    }
+  }
+}
+
+fn format_labels(labels: &Labels, le: Option<&str>) -> String {
+  let pairs = labels
+    .iter()
+    .map(|(label, value)| (*label, value.as_str()))
+    .chain(le.map(|le| ("le", le)))
+    .map(|(label, value)| {
+      let value = value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n");
+      format!("{label}=\"{value}\"")
+    })
+    .collect::<Vec<_>>();
+  if pairs.is_empty() {
+    String::new()
+  } else {
+    format!("{{{}}}", pairs.join(","))
+  }
+}
+
+/// When a request came in: one request-local timer, shared by the fairings
+/// timing requests.
+pub(crate) struct RequestStart(pub(crate) Instant);
+
+/// Counts and times every request, by the name of the route answering it
+/// (`none` for those no route matched).
+pub struct RequestMetrics;
+
+#[rocket::async_trait]
+impl Fairing for RequestMetrics {
+  fn info(&self) -> Info {
+    Info {
+      name: "Request metrics",
+      kind: Kind::Request | Kind::Response,
+    }
+  }
+
+  async fn on_request(&self, req: &mut Request<'_>, _: &mut Data<'_>) {
+    req.local_cache(|| RequestStart(Instant::now()));
+  }
+
+  async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+    let elapsed = req.local_cache(|| RequestStart(Instant::now())).0.elapsed();
+    let route = req
+      .route()
+      .and_then(|route| route.name.as_deref())
+      .unwrap_or("none");
+    let status = res.status().code.to_string();
+    METRICS.inc(
+      "ar5iv_http_requests_total",
+      &[("route", route), ("status", &status)],
+    );
+    METRICS.observe_duration(
+      "ar5iv_http_request_duration_seconds",
+      &[("route", route)],
+      elapsed,
+    );
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn counters_and_histograms_render_in_the_text_format() {
+    let metrics = Metrics::new();
+    metrics.cache_lookup("p:2105.04404", true);
+    metrics.cache_lookup("p:2105.04404", true);
+    metrics.cache_lookup("a:2105.04404/x1.png", false);
+    metrics.observe("ar5iv_assembled_paper_bytes", &[], 100_000.0);
+    metrics.observe("ar5iv_assembled_paper_bytes", &[], 1e9);
+    metrics.inc("ar5iv_html_fallbacks_total", &[("outcome", "quote\"d")]);
+    let text = metrics.render();
+    assert!(text.contains("# TYPE ar5iv_cache_requests_total counter\n"));
+    assert!(text.contains("ar5iv_cache_requests_total{keyspace=\"p\",result=\"hit\"} 2\n"));
+    assert!(text.contains("ar5iv_cache_requests_total{keyspace=\"a\",result=\"miss\"} 1\n"));
+    assert!(text.contains("ar5iv_assembled_paper_bytes_bucket{le=\"65536\"} 0\n"));
+    assert!(text.contains("ar5iv_assembled_paper_bytes_bucket{le=\"262144\"} 1\n"));
+    assert!(text.contains("ar5iv_assembled_paper_bytes_bucket{le=\"67108864\"} 1\n"));
+    assert!(text.contains("ar5iv_assembled_paper_bytes_bucket{le=\"+Inf\"} 2\n"));
+    assert!(text.contains("ar5iv_assembled_paper_bytes_count 2\n"));
+    assert!(text.contains("ar5iv_html_fallbacks_total{outcome=\"quote\\\"d\"} 1\n"));
+    // families without samples still get their header
+    assert!(text.contains("# TYPE ar5iv_skipped_assets_total counter\n"));
+  }
+}
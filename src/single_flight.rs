@@ -0,0 +1,94 @@
+use rocket::tokio::sync::OnceCell;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+/// Runs one computation per key at a time: callers arriving while it is in
+/// flight wait for it, and share its result -- e.g. the dozens of requests
+/// missing the cache for a paper that was just linked from a busy site.
+///
+/// Should the running caller go away (a dropped request), a waiting one takes
+/// the computation over. Results are only shared while in flight: once it is
+/// done, the next call for the key runs anew (by then, typically a cache hit).
+pub struct SingleFlight<T> {
+  calls: Mutex<HashMap<String, Arc<OnceCell<T>>>>,
+}
+
+impl<T: Clone> SingleFlight<T> {
+  pub fn new() -> Self {
+    SingleFlight {
+      calls: Mutex::new(HashMap::new()),
+    }
+  }
+
+  pub async fn run<F, Fut>(&self, key: &str, compute: F) -> T
+  where
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = T>,
+  {
+    let call = match self.calls.lock() {
+      Ok(mut calls) => calls
+        .entry(key.to_string())
+        .or_insert_with(|| Arc::new(OnceCell::new()))
+        .clone(),
+      // (a poisoned map only costs the sharing)
+      Err(_) => Arc::new(OnceCell::new()),
+    };
+    let result = call.get_or_init(compute).await.clone();
+    if let Ok(mut calls) = self.calls.lock() {
+      if calls
+        .get(key)
+        .is_some_and(|current| Arc::ptr_eq(current, &call))
+      {
+        calls.remove(key);
+      }
+    }
+    result
+  }
+
+  /// How many keys have a computation in flight.
+  pub fn len(&self) -> usize {
+    self.calls.lock().map_or(0, |calls| calls.len())
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+}
+
+impl<T: Clone> Default for SingleFlight<T> {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use rocket::tokio::time::{sleep, Duration};
+  use std::sync::atomic::{AtomicUsize, Ordering};
+
+  #[rocket::async_test]
+  async fn concurrent_calls_share_one_run() {
+    let flights = SingleFlight::new();
+    let runs = AtomicUsize::new(0);
+    let call = || {
+      flights.run("2105.04404", || async {
+        runs.fetch_add(1, Ordering::SeqCst);
+        sleep(Duration::from_millis(20)).await;
+        String::from("<html>")
+      })
+    };
+    let (a, b, c) = rocket::tokio::join!(call(), call(), call());
+    assert_eq!([a, b, c], ["<html>", "<html>", "<html>"]);
+    assert_eq!(runs.load(Ordering::SeqCst), 1);
+    assert!(flights.is_empty());
+
+    // once done, the next call runs anew
+    call().await;
+    assert_eq!(runs.load(Ordering::SeqCst), 2);
+    // and other keys never wait on it
+    let other = flights.run("math/0211159", || async { String::new() });
+    assert_eq!(other.await, "");
+  }
+}
@@ -0,0 +1,137 @@
+use rocket::http::ContentType;
+use std::path::Path;
+
+/// How much of a file's start `sniff_extension` needs to look at.
+pub const SNIFF_LEN: usize = 512;
+
+/// Magic bytes at the start of a file, and the extension of the format they mark.
+const SIGNATURES: &[(&[u8], &str)] = &[
+  (b"\x89PNG\r\n\x1a\n", "png"),
+  (b"\xff\xd8\xff", "jpg"),
+  (b"GIF87a", "gif"),
+  (b"GIF89a", "gif"),
+  (b"%PDF-", "pdf"),
+  (b"PK\x03\x04", "zip"),
+  (b"\x1f\x8b", "gz"),
+  (b"II*\x00", "tiff"),
+  (b"MM\x00*", "tiff"),
+  (b"\x1a\x45\xdf\xa3", "webm"),
+  (b"OggS", "ogg"),
+  (b"wOFF", "woff"),
+  (b"wOF2", "woff2"),
+];
+
+/// The extension of the format a file's first bytes (up to `SNIFF_LEN` of
+/// them) identify it as: images, documents, archives, media and fonts by
+/// their magic bytes, then SVG and HTML markup, then plain text.
+pub fn sniff_extension(head: &[u8]) -> Option<&'static str> {
+  let head = &head[..head.len().min(SNIFF_LEN)];
+  if let Some((_, extension)) = SIGNATURES.iter().find(|(magic, _)| head.starts_with(magic)) {
+    return Some(extension);
+  }
+  if head.len() >= 12 && &head[0..4] == b"RIFF" && &head[8..12] == b"WEBP" {
+    return Some("webp");
+  }
+  if head.len() >= 12 && &head[4..8] == b"ftyp" {
+    return Some("mp4");
+  }
+  // markup and text: valid UTF-8 (short of a character cut off at the end)
+  // without control characters
+  let text = match std::str::from_utf8(head) {
+    Ok(text) => text,
+    Err(e) if e.error_len().is_none() => std::str::from_utf8(&head[..e.valid_up_to()]).ok()?,
+    Err(_) => return None,
+  };
+  if text
+    .chars()
+    .any(|c| c.is_control() && !c.is_ascii_whitespace())
+  {
+    return None;
+  }
+  let markup = text
+    .trim_start_matches('\u{feff}')
+    .trim_start()
+    .to_ascii_lowercase();
+  if markup.starts_with("<svg") || (markup.starts_with("<?xml") && markup.contains("<svg")) {
+    Some("svg")
+  } else if markup.starts_with("<!doctype html") || markup.starts_with("<html") {
+    Some("html")
+  } else if text.is_empty() {
+    None
+  } else {
+    Some("txt")
+  }
+}
+
+/// The content type to serve a paper asset with: by its extension, else by
+/// sniffing its first bytes, else as opaque binary data.
+pub fn asset_content_type(filename: &str, head: &[u8]) -> ContentType {
+  extension_content_type(filename)
+    .or_else(|| sniff_extension(head).and_then(ContentType::from_extension))
+    .unwrap_or(ContentType::Binary)
+}
+
+/// Whether a request for a (missing) asset is one for an image, judging by
+/// its extension -- only those are answered with a placeholder image.
+pub fn is_image_request(filename: &str) -> bool {
+  extension_content_type(filename).is_some_and(|content_type| content_type.top() == "image")
+}
+
+fn extension_content_type(filename: &str) -> Option<ContentType> {
+  Path::new(filename)
+    .extension()
+    .and_then(|extension| extension.to_str())
+    .and_then(ContentType::from_extension)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn magic_bytes_are_recognized() {
+    for (head, extension) in [
+      (&b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"[..], "png"),
+      (b"\xff\xd8\xff\xe0\0\x10JFIF", "jpg"),
+      (b"GIF89a\x01\0\x01\0", "gif"),
+      (b"RIFF\x24\0\0\0WEBPVP8 ", "webp"),
+      (b"%PDF-1.5\n%\xe2\xe3", "pdf"),
+      (b"PK\x03\x04\x14\0", "zip"),
+      (b"\0\0\0\x18ftypmp42", "mp4"),
+      (b"wOF2\0\x01\0\0", "woff2"),
+      (
+        b"<?xml version=\"1.0\"?>\n<svg xmlns=\"http://www.w3.org/2000/svg\">",
+        "svg",
+      ),
+      (b"  <svg width=\"10\">", "svg"),
+      (b"<!DOCTYPE html><html>", "html"),
+      (b"\\documentclass{article}\n", "txt"),
+    ] {
+      assert_eq!(sniff_extension(head), Some(extension));
+    }
+    assert_eq!(sniff_extension(b""), None);
+    assert_eq!(sniff_extension(b"\0\x01\x02\x03binary"), None);
+  }
+
+  #[test]
+  fn extensions_win_over_sniffing() {
+    assert_eq!(asset_content_type("x1.png", b"GIF89a"), ContentType::PNG);
+    assert_eq!(asset_content_type("x1.css", b"body {}"), ContentType::CSS);
+    assert_eq!(asset_content_type("x1", b"GIF89a"), ContentType::GIF);
+    assert_eq!(
+      asset_content_type("figure.data", b"%PDF-1.5"),
+      ContentType::PDF
+    );
+    assert_eq!(asset_content_type("x1", b"\0\x01"), ContentType::Binary);
+  }
+
+  #[test]
+  fn only_image_misses_get_a_placeholder() {
+    assert!(is_image_request("x1.png"));
+    assert!(is_image_request("figures/x1.svg"));
+    assert!(!is_image_request("style.css"));
+    assert!(!is_image_request("script.js"));
+    assert!(!is_image_request("page.html"));
+    assert!(!is_image_request("x1"));
+  }
+}
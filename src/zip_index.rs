@@ -0,0 +1,342 @@
+use flate2::read::DeflateDecoder;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::SystemTime;
+use zip::{CompressionMethod, ZipArchive};
+
+use crate::assemble_error::AssembleError;
+use crate::cache::SIXTY_FOUR_MIB;
+use crate::metrics::METRICS;
+
+/// How many bundles' indexes are kept: those of the papers being read now,
+/// their figures requested one after the other.
+const CAPACITY: usize = 256;
+
+pub static ZIP_INDEXES: LazyLock<ZipIndexes> = LazyLock::new(|| ZipIndexes::new(CAPACITY));
+
+/// Where an entry of a bundle lies, and how it is stored.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexedEntry {
+  /// The offset of its data in the bundle, past its local header.
+  pub data_start: u64,
+  pub compressed_size: u64,
+  pub size: u64,
+  pub compression: CompressionMethod,
+  pub crc32: u32,
+  pub encrypted: bool,
+}
+
+impl IndexedEntry {
+  /// Stored entries are their bytes in the bundle, as is: they can be served
+  /// as a range of its file.
+  pub fn is_stored(&self) -> bool {
+    self.compression == CompressionMethod::Stored && !self.encrypted
+  }
+
+  /// The contents of the entry from `offset` on, read from `file` (blocking).
+  /// A stored entry is seeked into; a deflated one decompressed past `offset`.
+  pub fn reader<'f>(
+    &self,
+    file: &'f File,
+    offset: u64,
+  ) -> Result<Box<dyn Read + 'f>, AssembleError> {
+    if self.encrypted {
+      return Err(AssembleError::Corrupt(String::from("encrypted entry")));
+    }
+    let mut file = file;
+    match self.compression {
+      CompressionMethod::Stored => {
+        let offset = offset.min(self.compressed_size);
+        file.seek(SeekFrom::Start(self.data_start + offset))?;
+        Ok(Box::new(file.take(self.compressed_size - offset)))
+      }
+      CompressionMethod::Deflated => {
+        file.seek(SeekFrom::Start(self.data_start))?;
+        let mut entry = DeflateDecoder::new(file.take(self.compressed_size)).take(self.size);
+        io::copy(&mut (&mut entry).take(offset), &mut io::sink())?;
+        Ok(Box::new(entry))
+      }
+      method => Err(AssembleError::Corrupt(format!(
+        "unsupported compression method {method}"
+      ))),
+    }
+  }
+
+  /// The whole entry, checked against its CRC-32 (blocking).
+  pub fn read(&self, file: &File) -> Result<Vec<u8>, AssembleError> {
+    let mut contents = Vec::with_capacity(self.size.min(SIXTY_FOUR_MIB) as usize);
+    self.reader(file, 0)?.read_to_end(&mut contents)?;
+    if contents.len() as u64 != self.size || crc32fast::hash(&contents) != self.crc32 {
+      return Err(AssembleError::Corrupt(String::from("checksum mismatch")));
+    }
+    Ok(contents)
+  }
+}
+
+/// The entries of a bundle, read from its central directory once, for as
+/// long as the bundle keeps the modification time and size it had then.
+#[derive(Debug)]
+pub struct ZipIndex {
+  modified: Option<SystemTime>,
+  len: u64,
+  entries: HashMap<String, IndexedEntry>,
+}
+
+impl ZipIndex {
+  pub fn read<R: Read + Seek>(
+    reader: R,
+    modified: Option<SystemTime>,
+    len: u64,
+  ) -> Result<Self, AssembleError> {
+    let mut zip = ZipArchive::new(reader)?;
+    let mut entries = HashMap::with_capacity(zip.len());
+    for i in 0..zip.len() {
+      // (a raw read doesn't decompress, but finds where the data starts)
+      let entry = zip.by_index_raw(i)?;
+      let Some(data_start) = entry.data_start() else {
+        continue;
+      };
+      entries.insert(
+        entry.name().to_string(),
+        IndexedEntry {
+          data_start,
+          compressed_size: entry.compressed_size(),
+          size: entry.size(),
+          compression: entry.compression(),
+          crc32: entry.crc32(),
+          encrypted: entry.encrypted(),
+        },
+      );
+    }
+    Ok(ZipIndex {
+      modified,
+      len,
+      entries,
+    })
+  }
+
+  fn is_current(&self, modified: Option<SystemTime>, len: u64) -> bool {
+    self.modified == modified && self.len == len
+  }
+
+  pub fn entry(&self, name: &str) -> Option<&IndexedEntry> {
+    self.entries.get(name)
+  }
+
+  /// The uncompressed size of an entry, or of all of them. Entries too large
+  /// to be buffered are streamed, and count for their buffer cap only.
+  pub fn uncompressed_size(&self, entry: Option<&str>) -> u64 {
+    match entry {
+      Some(name) => self
+        .entry(name)
+        .map_or(0, |entry| entry.size.min(SIXTY_FOUR_MIB)),
+      None => self
+        .entries
+        .values()
+        .fold(0, |total: u64, entry| total.saturating_add(entry.size)),
+    }
+  }
+}
+
+/// The indexes of the bundles read lately, so that serving an asset seeks
+/// straight to its entry instead of parsing the bundle's central directory
+/// anew. Bounded to `capacity` bundles, the least recently used going first;
+/// a bundle replaced by a data update is indexed again.
+pub struct ZipIndexes {
+  capacity: usize,
+  state: Mutex<IndexesState>,
+}
+
+#[derive(Default)]
+struct IndexesState {
+  /// Each index, with when it was last used.
+  indexes: HashMap<PathBuf, (Arc<ZipIndex>, u64)>,
+  clock: u64,
+}
+
+impl ZipIndexes {
+  pub fn new(capacity: usize) -> Self {
+    ZipIndexes {
+      capacity: capacity.max(1),
+      state: Mutex::new(IndexesState::default()),
+    }
+  }
+
+  /// Opens a bundle, with its index (blocking). Each caller gets a file of
+  /// its own, to seek in as it pleases.
+  pub fn open(&self, path: &Path) -> Result<(File, Arc<ZipIndex>), AssembleError> {
+    let file = File::open(path)?;
+    // (the index is checked against the file opened, whatever replaced it since)
+    let metadata = file.metadata()?;
+    let (modified, len) = (metadata.modified().ok(), metadata.len());
+    if let Some(index) = self.lookup(path, modified, len) {
+      METRICS.inc("ar5iv_zip_index_lookups_total", &[("result", "hit")]);
+      return Ok((file, index));
+    }
+    METRICS.inc("ar5iv_zip_index_lookups_total", &[("result", "miss")]);
+    let index = Arc::new(ZipIndex::read(BufReader::new(&file), modified, len)?);
+    self.insert(path, index.clone());
+    Ok((file, index))
+  }
+
+  /// Opens a bundle positioned at the data of its entry `name`, when that
+  /// entry is stored: its `(file, start, len)`, to be served as a file range.
+  pub fn open_stored(&self, path: &Path, name: &str) -> Option<(File, u64, u64)> {
+    let (mut file, index) = self.open(path).ok()?;
+    let entry = index.entry(name).filter(|entry| entry.is_stored())?;
+    file.seek(SeekFrom::Start(entry.data_start)).ok()?;
+    Some((file, entry.data_start, entry.size))
+  }
+
+  fn lookup(&self, path: &Path, modified: Option<SystemTime>, len: u64) -> Option<Arc<ZipIndex>> {
+    let mut state = self.state.lock().ok()?;
+    state.clock += 1;
+    let now = state.clock;
+    let (index, last_used) = state.indexes.get_mut(path)?;
+    if !index.is_current(modified, len) {
+      return None;
+    }
+    *last_used = now;
+    Some(index.clone())
+  }
+
+  fn insert(&self, path: &Path, index: Arc<ZipIndex>) {
+    let Ok(mut state) = self.state.lock() else {
+      return;
+    };
+    if !state.indexes.contains_key(path) && state.indexes.len() >= self.capacity {
+      let oldest = state
+        .indexes
+        .iter()
+        .min_by_key(|(_, (_, last_used))| *last_used)
+        .map(|(path, _)| path.clone());
+      if let Some(oldest) = oldest {
+        state.indexes.remove(&oldest);
+      }
+    }
+    let now = state.clock;
+    state.indexes.insert(path.to_path_buf(), (index, now));
+  }
+
+  /// How many bundles are indexed.
+  pub fn len(&self) -> usize {
+    self.state.lock().map_or(0, |state| state.indexes.len())
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::io::Write;
+  use zip::write::SimpleFileOptions;
+
+  /// A temp bundle path of this test run's own.
+  fn bundle_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("{name}_{}.zip", std::process::id()))
+  }
+
+  fn write_bundle(path: &Path, figure: &[u8]) {
+    let mut zip = zip::ZipWriter::new(File::create(path).unwrap());
+    let deflated = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    zip.start_file("2105.04404.html", deflated).unwrap();
+    zip.write_all(&b"<html>".repeat(1000)).unwrap();
+    let stored = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+    zip.start_file("x1.png", stored).unwrap();
+    zip.write_all(figure).unwrap();
+    zip.finish().unwrap();
+  }
+
+  #[test]
+  fn entries_are_read_where_they_lie() {
+    let path = bundle_path("ar5iv_zip_index_entries_test");
+    let figure: Vec<u8> = (0..5000).map(|i| (i % 251) as u8).collect();
+    write_bundle(&path, &figure);
+    let indexes = ZipIndexes::new(4);
+    let (file, index) = indexes.open(&path).unwrap();
+
+    let page = index.entry("2105.04404.html").unwrap();
+    assert!(!page.is_stored());
+    assert_eq!(page.read(&file).unwrap(), b"<html>".repeat(1000));
+    let mut tail = Vec::new();
+    page
+      .reader(&file, 5994)
+      .unwrap()
+      .read_to_end(&mut tail)
+      .unwrap();
+    assert_eq!(tail, b"<html>");
+
+    let png = index.entry("x1.png").unwrap();
+    assert!(png.is_stored());
+    assert_eq!(png.read(&file).unwrap(), figure);
+    let mut part = Vec::new();
+    png
+      .reader(&file, 1000)
+      .unwrap()
+      .take(10)
+      .read_to_end(&mut part)
+      .unwrap();
+    assert_eq!(part, &figure[1000..1010]);
+    let (mut stored, start, len) = indexes.open_stored(&path, "x1.png").unwrap();
+    assert_eq!((start, len), (png.data_start, 5000));
+    let mut raw = vec![0; 5000];
+    stored.read_exact(&mut raw).unwrap();
+    assert_eq!(raw, figure);
+    assert!(indexes.open_stored(&path, "2105.04404.html").is_none());
+
+    assert!(index.entry("missing.png").is_none());
+    std::fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn indexes_are_reused_until_the_bundle_changes() {
+    let path = bundle_path("ar5iv_zip_index_reuse_test");
+    write_bundle(&path, &[1; 100]);
+    let indexes = ZipIndexes::new(2);
+    let (_, first) = indexes.open(&path).unwrap();
+    let (_, again) = indexes.open(&path).unwrap();
+    assert!(Arc::ptr_eq(&first, &again));
+
+    // a data update rewrites the bundle (here, to another size)
+    write_bundle(&path, &[2; 200]);
+    let (file, updated) = indexes.open(&path).unwrap();
+    assert!(!Arc::ptr_eq(&first, &updated));
+    assert_eq!(
+      updated.entry("x1.png").unwrap().read(&file).unwrap(),
+      [2; 200]
+    );
+    assert_eq!(indexes.len(), 1);
+
+    // the least recently used bundle makes room for a third one
+    let second = bundle_path("ar5iv_zip_index_reuse_test_2");
+    let third = bundle_path("ar5iv_zip_index_reuse_test_3");
+    write_bundle(&second, &[3; 100]);
+    write_bundle(&third, &[4; 100]);
+    indexes.open(&second).unwrap();
+    indexes.open(&path).unwrap();
+    indexes.open(&third).unwrap();
+    assert_eq!(indexes.len(), 2);
+    let (_, kept) = indexes.open(&path).unwrap();
+    assert!(Arc::ptr_eq(&updated, &kept));
+    for bundle in [path, second, third] {
+      std::fs::remove_file(bundle).unwrap();
+    }
+  }
+
+  #[test]
+  fn damaged_entries_fail_their_checksum() {
+    let path = bundle_path("ar5iv_zip_index_damage_test");
+    write_bundle(&path, &[5; 100]);
+    let (file, index) = ZipIndexes::new(1).open(&path).unwrap();
+    let mut png = index.entry("x1.png").unwrap().clone();
+    png.crc32 ^= 1;
+    assert!(matches!(png.read(&file), Err(AssembleError::Corrupt(_))));
+    std::fs::remove_file(&path).unwrap();
+  }
+}
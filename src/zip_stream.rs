@@ -0,0 +1,139 @@
+use rocket::tokio::io::{duplex, AsyncRead, AsyncSeek, AsyncWriteExt, DuplexStream, ReadBuf};
+use rocket::tokio::runtime::Handle;
+use rocket::tokio::task::spawn_blocking;
+use std::io::{self, Read, SeekFrom};
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use crate::zip_index::ZIP_INDEXES;
+
+/// The chunk size entries are decompressed in.
+const CHUNK_SIZE: usize = 65_536;
+/// How much decompressed data may wait for a slow client, per response.
+const PIPE_CAPACITY: usize = 4 * CHUNK_SIZE;
+
+/// A ZIP entry, decompressed chunk by chunk onto a response body.
+///
+/// The decompression runs in a blocking task and writes into a bounded pipe:
+/// when the client falls behind, the pipe fills up and the task waits, so an
+/// asset of any size is served within `PIPE_CAPACITY` bytes of memory. When
+/// the client goes away, the pipe closes and the task stops.
+pub struct ZipEntryStream {
+  pipe: DuplexStream,
+  len: u64,
+  pos: u64,
+}
+
+/// Streams `len` bytes of the entry `name` of the ZIP at `bundle`, starting
+/// `skip` bytes into its (decompressed) contents.
+pub fn stream_zip_entry(bundle: PathBuf, name: String, skip: u64, len: u64) -> ZipEntryStream {
+  let (pipe, mut writer) = duplex(PIPE_CAPACITY);
+  spawn_blocking(move || -> io::Result<()> {
+    let runtime = Handle::current();
+    let (zipf, index) = ZIP_INDEXES.open(&bundle).map_err(io::Error::other)?;
+    let entry = index
+      .entry(&name)
+      .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))?;
+    // (a stored entry is seeked into, deflated data only decompressed past)
+    let mut entry = entry
+      .reader(&zipf, skip)
+      .map_err(io::Error::other)?
+      .take(len);
+    let mut chunk = vec![0; CHUNK_SIZE];
+    loop {
+      let read = entry.read(&mut chunk)?;
+      if read == 0 {
+        break;
+      }
+      // waits for the client whenever the pipe is full
+      runtime.block_on(writer.write_all(&chunk[..read]))?;
+    }
+    // (on an error the writer is dropped and the body ends short, which the
+    // client sees as a truncated response against its Content-Length)
+    Ok(())
+  });
+  ZipEntryStream { pipe, len, pos: 0 }
+}
+
+impl AsyncRead for ZipEntryStream {
+  fn poll_read(
+    self: Pin<&mut Self>,
+    cx: &mut Context<'_>,
+    buf: &mut ReadBuf<'_>,
+  ) -> Poll<io::Result<()>> {
+    let this = self.get_mut();
+    let filled = buf.filled().len();
+    ready!(Pin::new(&mut this.pipe).poll_read(cx, buf))?;
+    this.pos += (buf.filled().len() - filled) as u64;
+    Poll::Ready(Ok(()))
+  }
+}
+
+/// A stream can't seek: this only answers position queries, which is all a
+/// body of known size is asked.
+impl AsyncSeek for ZipEntryStream {
+  fn start_seek(self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+    let this = self.get_mut();
+    let target = match position {
+      SeekFrom::Start(offset) => Some(offset),
+      SeekFrom::End(offset) => this.len.checked_add_signed(offset),
+      SeekFrom::Current(offset) => this.pos.checked_add_signed(offset),
+    };
+    if target == Some(this.pos) {
+      Ok(())
+    } else {
+      Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "a streamed ZIP entry can't seek",
+      ))
+    }
+  }
+
+  fn poll_complete(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+    Poll::Ready(Ok(self.pos))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use rocket::tokio::io::AsyncReadExt;
+  use std::fs::File;
+  use std::io::Write;
+  use zip::write::SimpleFileOptions;
+
+  fn write_bundle(name: &str, contents: &[u8]) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("{name}_{}.zip", std::process::id()));
+    let mut zip = zip::ZipWriter::new(File::create(&path).unwrap());
+    zip
+      .start_file("large.bin", SimpleFileOptions::default())
+      .unwrap();
+    zip.write_all(contents).unwrap();
+    zip.finish().unwrap();
+    path
+  }
+
+  #[rocket::async_test]
+  async fn entries_stream_in_full_and_in_part() {
+    // several chunks' worth, so that the pipe fills up along the way
+    let contents: Vec<u8> = (0..10 * CHUNK_SIZE).map(|i| (i % 251) as u8).collect();
+    let bundle = write_bundle("ar5iv_zip_stream_test", &contents);
+    let len = contents.len() as u64;
+
+    let mut whole = Vec::new();
+    stream_zip_entry(bundle.clone(), String::from("large.bin"), 0, len)
+      .read_to_end(&mut whole)
+      .await
+      .unwrap();
+    assert_eq!(whole, contents);
+
+    let mut part = Vec::new();
+    stream_zip_entry(bundle.clone(), String::from("large.bin"), 100_000, 1_000)
+      .read_to_end(&mut part)
+      .await
+      .unwrap();
+    assert_eq!(part, &contents[100_000..101_000]);
+    std::fs::remove_file(&bundle).unwrap();
+  }
+}